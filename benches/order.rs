@@ -0,0 +1,87 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use isoperm::config::{Config, GroupOrder};
+use isoperm::wrapper::{Isoperm, Var};
+
+type Bag = Vec<(&'static str, Vec<Var<usize>>)>;
+
+// A bag of `k` disjoint edges, plus a pair linking two edges in the source but
+// the two ends of one edge in the target. The bags are not isomorphic, which
+// is only found out after placing the pair.
+fn linked_edges(k: usize, linked: bool) -> (Bag, HashMap<Var<usize>, ()>) {
+    let mut constraints: Bag =
+        (0..k).map(|i| ("E", vec![Var::Local(2 * i), Var::Local(2 * i + 1)])).collect();
+    constraints.push(("P", vec![Var::Local(0), Var::Local(if linked { 2 } else { 1 })]));
+    (constraints, (0..2 * k).map(|i| (Var::Local(i), ())).collect())
+}
+
+// A random bag of binary constraints over `n` locals with a few identifiers,
+// and the same bag with the locals renamed.
+fn random_graph(n: usize, m: usize, seed: u64) -> (Bag, Bag, HashMap<Var<usize>, ()>) {
+    let mut state = seed;
+    let mut next = move |bound: usize| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as usize % bound
+    };
+    let names = ["A", "B", "C", "D"];
+    let source: Bag = (0..m)
+        .map(|_| (names[next(names.len())], vec![Var::Local(next(n)), Var::Local(next(n))]))
+        .collect();
+    let target = source
+        .iter()
+        .map(|(r, vs)| {
+            let renamed = vs.iter().map(|v| match *v {
+                Var::Local(i) => Var::Local((i * 7 + 3) % n),
+                _ => unreachable!(),
+            });
+            (*r, renamed.collect())
+        })
+        .collect();
+    (source, target, (0..n).map(|i| (Var::Local(i), ())).collect())
+}
+
+fn measure(name: &str, run: impl Fn(GroupOrder) -> usize) {
+    for order in [GroupOrder::Input, GroupOrder::Smallest, GroupOrder::Connected] {
+        let start = Instant::now();
+        let mut count = 0;
+        let mut rounds = 0;
+        while start.elapsed() < Duration::from_millis(500) {
+            count = run(order);
+            rounds += 1;
+        }
+        println!(
+            "{:<16} {:<10} {:>8} permutations {:>12.3?} per round",
+            name,
+            format!("{:?}", order),
+            count,
+            start.elapsed() / rounds
+        );
+    }
+}
+
+fn main() {
+    measure("linked_edges", |order| {
+        let (source_constraints, source_variables) = linked_edges(7, true);
+        let (target_constraints, target_variables) = linked_edges(7, false);
+        Isoperm::with_config(
+            source_constraints,
+            source_variables,
+            target_constraints,
+            target_variables,
+            Config::new().order(order),
+        )
+        .map_or(0, |mut isoperm| isoperm.result().count())
+    });
+    measure("random_graph", |order| {
+        let (source_constraints, target_constraints, variables) = random_graph(12, 18, 7);
+        Isoperm::with_config(
+            source_constraints,
+            variables.clone(),
+            target_constraints,
+            variables,
+            Config::new().order(order),
+        )
+        .map_or(0, |mut isoperm| isoperm.result().count())
+    });
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::iter::once;
+use std::time::{Duration, Instant};
+
+use isoperm::wrapper::{Isoperm, Var};
+
+type Bag = Vec<(&'static str, Vec<Var<usize>>)>;
+type Types = HashMap<Var<usize>, ()>;
+
+// A path over `n` locals, with a chord from each local and a
+// marker on the first one, and the same bag with the locals renamed. The only
+// permutation is found without backtracking, so the time is spent on
+// bookkeeping.
+fn rigid_bag(n: usize) -> (Bag, Types, Bag, Types) {
+    let source: Bag = once(("S", vec![Var::Local(0)]))
+        .chain((1..n).map(|i| ("E", vec![Var::Local(i - 1), Var::Local(i)])))
+        .chain((0..n).map(|i| ("C", vec![Var::Local(i), Var::Local((i * 7919 + 13) % n)])))
+        .collect();
+    let rename = |i: usize| (i * 7 + 3) % n;
+    let target = source
+        .iter()
+        .map(|(r, vs)| {
+            let renamed = vs.iter().map(|v| match *v {
+                Var::Local(i) => Var::Local(rename(i)),
+                _ => unreachable!(),
+            });
+            (*r, renamed.collect())
+        })
+        .collect();
+    (
+        source,
+        (0..n).map(|i| (Var::Local(i), ())).collect(),
+        target,
+        (0..n).map(|i| (Var::Local(rename(i)), ())).collect(),
+    )
+}
+
+fn measure(name: &str, n: usize) {
+    let (source_constraints, source_variables, target_constraints, target_variables) = rigid_bag(n);
+    let start = Instant::now();
+    let mut rounds = 0;
+    let mut count = 0;
+    while rounds == 0 || start.elapsed() < Duration::from_millis(500) {
+        let mut isoperm = Isoperm::new(
+            source_constraints.clone(),
+            source_variables.clone(),
+            target_constraints.clone(),
+            target_variables.clone(),
+        )
+        .unwrap();
+        count = isoperm.result().count();
+        rounds += 1;
+    }
+    println!(
+        "{:<10} {:>6} locals {:>6} constraints {:>4} permutations {:>12.3?} per round",
+        name,
+        n,
+        source_constraints.len(),
+        count,
+        start.elapsed() / rounds
+    );
+}
+
+fn main() {
+    measure("small", 251);
+    measure("medium", 1009);
+    measure("large", 4001);
+}
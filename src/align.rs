@@ -0,0 +1,359 @@
+use crate::environment::Environment;
+use crate::search::{Context, Interrupt, Limit};
+use crate::statement::{Constraint, Variable};
+use crate::wrapper::{Builder, Isoperm, Lookup, Var};
+use bimap::BiMap;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// A constraint as given to `Isoperm::new`.
+pub type Statement<R, U, V = U, W = U> = (R, Vec<Var<U, V, W>>);
+/// A source constraint with the target constraint it is matched to.
+pub type StatementPair<R, U, V = U, W = U> = (Statement<R, U, V, W>, Statement<R, U, V, W>);
+
+/// # The alignment struct.
+/// An alignment matches as many source constraints as possible to distinct
+/// target constraints, under a single mapping of local variables to local
+/// variables of the same types. Global variables are paired and expression
+/// variables are matched to anything, as in `Isoperm::new`, where renamed
+/// globals are mapped as local variables are.
+pub struct Alignment<R, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The pairs of matched source and target constraints.
+    pub matched: Vec<StatementPair<R, U, V, W>>,
+    /// The mapping of the local variables used by the matched constraints,
+    /// and of the renamed globals.
+    pub mapping: BiMap<Var<U, V, W>, Var<U, V, W>>,
+    /// The source constraints and local variables left unmatched.
+    pub source: Remainder<R, U, V, W>,
+    /// The target constraints and local variables left unmatched.
+    pub target: Remainder<R, U, V, W>,
+    /// The reason why the search was cut short, or `None` if the alignment is
+    /// known to be the largest.
+    pub interrupted: Option<Interrupt>,
+}
+
+/// The constraints and local variables of a side left out of an alignment.
+pub struct Remainder<R, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The unmatched constraints, in the order they are given.
+    pub constraints: Vec<Statement<R, U, V, W>>,
+    /// The unmapped local variables.
+    pub locals: Vec<Var<U, V, W>>,
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Clone + Eq + Hash,
+    T: Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    /// Find the largest alignment of the inputs, which need not have any
+    /// permutation, where the optional constraints are left out. The search is
+    /// bounded by the limit as a whole, in which case the largest alignment
+    /// found is returned. Returns an error if some constraint uses an
+    /// undeclared variable, the globals cannot be paired, or the inputs have
+    /// forbidden constraints, which are not aligned.
+    pub fn align(self, limit: Limit) -> Result<Alignment<R, U, V, W>, String> {
+        let instance = Instance::new(self)?;
+        let mut context = Context::new(&limit, None, None);
+        let (pairs, interrupted) = instance.largest(0..instance.source.len(), &mut context);
+        let Instance { source_statements, target_statements, source, target, .. } = &instance;
+        let mut environment = instance.environment.clone();
+        pairs.iter().for_each(|&(s, t)| {
+            environment.unify(&source[s], &target[t]);
+        });
+        let lookup = |translation: &BiMap<Variable, Var<U, V, W>>, v: &Variable| {
+            translation.get_by_left(v).unwrap().clone()
+        };
+        let (source_translation, target_translation) =
+            (&instance.source_translation, &instance.target_translation);
+        let mapping: BiMap<_, _> = environment
+            .pairs()
+            .filter(|(v, _)| matches!(v, Variable::Local(_)))
+            .map(|(v, u)| (lookup(source_translation, &v), lookup(target_translation, &u)))
+            .collect();
+        let remainder = |statements: &[Statement<R, U, V, W>],
+                         matched: Vec<usize>,
+                         translation: &BiMap<Variable, Var<U, V, W>>,
+                         mapped: &dyn Fn(&Var<U, V, W>) -> bool| {
+            let constraints = statements
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| !matched.contains(i))
+                .map(|(_, statement)| statement.clone())
+                .collect();
+            let locals = translation
+                .iter()
+                .sorted_by_key(|(v, _)| v.index())
+                .map(|(_, v)| v)
+                .filter(|v| matches!(v, Var::Local(_)) && !mapped(v))
+                .cloned()
+                .collect();
+            Remainder { constraints, locals }
+        };
+        let matched = pairs
+            .iter()
+            .map(|&(s, t)| (source_statements[s].clone(), target_statements[t].clone()))
+            .collect();
+        let (source_matched, target_matched) = pairs.into_iter().unzip();
+        Ok(Alignment {
+            matched,
+            source: remainder(source_statements, source_matched, source_translation, &|v| {
+                mapping.contains_left(v)
+            }),
+            target: remainder(target_statements, target_matched, target_translation, &|v| {
+                mapping.contains_right(v)
+            }),
+            mapping,
+            interrupted,
+        })
+    }
+}
+
+// The inputs of an alignment, with the constraints told apart by their kinds.
+pub(crate) struct Instance<R, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    pub(crate) source_statements: Vec<Statement<R, U, V, W>>,
+    pub(crate) target_statements: Vec<Statement<R, U, V, W>>,
+    pub(crate) source: Vec<Constraint>,
+    source_key: Vec<usize>,
+    target: Vec<Constraint>,
+    target_key: Vec<usize>,
+    source_translation: Lookup<U, V, W>,
+    target_translation: Lookup<U, V, W>,
+    environment: Environment,
+}
+
+impl<R, U, V, W> Instance<R, U, V, W>
+where
+    R: Clone + Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    // Transform the inputs as in `Builder::align`.
+    pub(crate) fn new<T>(builder: Builder<R, T, U, V, W>) -> Result<Self, String>
+    where
+        T: Eq + Hash,
+    {
+        if !builder.source_forbidden.is_empty() || !builder.target_forbidden.is_empty() {
+            return Err(String::from("Unsupported inputs for alignment."));
+        }
+        let Builder {
+            source: (source_statements, source_variables),
+            target: (target_statements, target_variables),
+            aliases,
+            config,
+            ..
+        } = builder;
+        let (source_native_variables, target_native_variables, global) =
+            Isoperm::prepare(source_variables, target_variables, &aliases, config.renaming)?;
+        let (source_types, source_translation) = Isoperm::split_mapping(source_native_variables);
+        let (target_types, target_translation) = Isoperm::split_mapping(target_native_variables);
+        let mut record = HashMap::new();
+        let source = Isoperm::transform_constraints(
+            source_statements.clone(),
+            &source_translation,
+            &mut record,
+        )?;
+        let target = Isoperm::transform_constraints(
+            target_statements.clone(),
+            &target_translation,
+            &mut record,
+        )?;
+        // Tell apart the constraints by their signatures and argument types,
+        // and the locals by their types.
+        let mut kind = HashMap::new();
+        let source_key = kinds(&source, &source_types, &mut kind);
+        let target_key = kinds(&target, &target_types, &mut kind);
+        let mut class = HashMap::new();
+        let colouring = (colours(&source_types, &mut class), colours(&target_types, &mut class));
+        let numbered = |types: &HashMap<Variable, (bool, T)>| {
+            types.keys().copied().sorted_by_key(Variable::index).collect_vec()
+        };
+        let mut environment =
+            Environment::new(numbered(&source_types), numbered(&target_types), &global);
+        environment.colour(colouring);
+        Ok(Self {
+            source_statements,
+            target_statements,
+            source,
+            source_key,
+            target,
+            target_key,
+            source_translation,
+            target_translation,
+            environment,
+        })
+    }
+
+    // Find the largest set of pairs of the given source constraints and of
+    // target constraints.
+    pub(crate) fn largest(
+        &self,
+        subset: impl IntoIterator<Item = usize>,
+        context: &mut Context,
+    ) -> (Vec<(usize, usize)>, Option<Interrupt>) {
+        largest(
+            &self.source,
+            &self.source_key,
+            subset.into_iter().collect(),
+            &self.target,
+            &self.target_key,
+            self.environment.clone(),
+            context,
+        )
+    }
+}
+
+// Number the kinds of constraints by their signatures and argument types.
+fn kinds<'t, T: Eq + Hash>(
+    constraints: &[Constraint],
+    types: &'t HashMap<Variable, T>,
+    kind: &mut HashMap<(usize, Vec<&'t T>), usize>,
+) -> Vec<usize> {
+    constraints
+        .iter()
+        .map(|c| {
+            let next = kind.len();
+            let key = (c.signature(), c.argument().iter().map(|v| &types[v]).collect());
+            *kind.entry(key).or_insert(next)
+        })
+        .collect()
+}
+
+// Number the types of the variables, in the order of the variables.
+fn colours<'t, T: Eq + Hash>(
+    types: &'t HashMap<Variable, T>,
+    class: &mut HashMap<&'t T, u32>,
+) -> Vec<u32> {
+    types
+        .iter()
+        .sorted_by_key(|(v, _)| v.index())
+        .map(|(_, t)| {
+            let next = class.len() as u32;
+            *class.entry(t).or_insert(next)
+        })
+        .collect()
+}
+
+// Find the largest set of pairs of source constraints in the subset and target
+// constraints of the same kinds that unify under the same bindings, by branch
+// and bound. The source constraints are either matched or left out in order,
+// and a subtree is pruned if it cannot match more constraints than the largest
+// set found so far.
+fn largest(
+    source: &[Constraint],
+    source_key: &[usize],
+    subset: Vec<usize>,
+    target: &[Constraint],
+    target_key: &[usize],
+    mut environment: Environment,
+    context: &mut Context,
+) -> (Vec<(usize, usize)>, Option<Interrupt>) {
+    let kinds = source_key.iter().chain(target_key).max().map_or(0, |k| k + 1);
+    let mut search = Search {
+        source,
+        left: subset.iter().map(|&s| source_key[s]).counts(),
+        order: subset.into_iter().sorted_by_key(|&s| source_key[s]).collect(),
+        source_key,
+        target,
+        candidates: (0..kinds)
+            .map(|k| (0..target.len()).filter(|&t| target_key[t] == k).collect())
+            .collect(),
+        // Identical target constraints are matched in order.
+        twin: {
+            let mut last = HashMap::new();
+            target.iter().enumerate().map(|(t, c)| last.insert(c, t)).collect()
+        },
+        used: vec![false; target.len()],
+        free: target_key.iter().copied().counts(),
+        current: Vec::new(),
+        best: Vec::new(),
+    };
+    let interrupted = search.descend(0, &mut environment, context).err();
+    (search.best, interrupted)
+}
+
+// The state of the search for the largest alignment.
+struct Search<'c> {
+    source: &'c [Constraint],
+    // The source constraints in the order they are decided, by their kinds.
+    order: Vec<usize>,
+    source_key: &'c [usize],
+    target: &'c [Constraint],
+    // The target constraints of each kind.
+    candidates: Vec<Vec<usize>>,
+    twin: Vec<Option<usize>>,
+    used: Vec<bool>,
+    // The numbers of undecided source constraints and of unused target
+    // constraints of each kind.
+    left: HashMap<usize, usize>,
+    free: HashMap<usize, usize>,
+    current: Vec<(usize, usize)>,
+    best: Vec<(usize, usize)>,
+}
+
+impl Search<'_> {
+    fn descend(
+        &mut self,
+        index: usize,
+        environment: &mut Environment,
+        context: &mut Context,
+    ) -> Result<(), Interrupt> {
+        context.tick()?;
+        if index == self.order.len() {
+            if self.current.len() > self.best.len() {
+                self.best = self.current.clone();
+            }
+            return Ok(());
+        }
+        let free = |k| self.free.get(k).copied().unwrap_or(0);
+        let bound: usize = self.left.iter().map(|(k, &left)| left.min(free(k))).sum();
+        if self.current.len() + bound <= self.best.len() {
+            return Ok(());
+        }
+        let s = self.order[index];
+        let kind = self.source_key[s];
+        *self.left.get_mut(&kind).unwrap() -= 1;
+        for i in 0..self.candidates[kind].len() {
+            let t = self.candidates[kind][i];
+            if self.used[t] || self.twin[t].is_some_and(|previous| !self.used[previous]) {
+                continue;
+            }
+            let mark = environment.mark();
+            if !environment.unify(&self.source[s], &self.target[t]) {
+                continue;
+            }
+            self.used[t] = true;
+            *self.free.get_mut(&kind).unwrap() -= 1;
+            self.current.push((s, t));
+            let searched = self.descend(index + 1, environment, context);
+            self.current.pop();
+            *self.free.get_mut(&kind).unwrap() += 1;
+            self.used[t] = false;
+            environment.undo(mark);
+            searched?;
+        }
+        // Leave the source constraint unmatched.
+        let searched = self.descend(index + 1, environment, context);
+        *self.left.get_mut(&kind).unwrap() += 1;
+        searched
+    }
+}
@@ -0,0 +1,123 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+use crate::statement::{Constraint, Variable};
+
+const VERSION: u64 = 2;
+
+/// # The enumeration checkpoint struct.
+/// A checkpoint is a snapshot of the enumeration state of an `Isoperm`
+/// instance, taken between two solutions. It can be written out with
+/// `to_string()`, parsed back with `parse()`, and resumed by another `Isoperm`
+/// instance constructed from the same inputs, possibly in another process.
+/// Resuming relies on the `Hash` implementations of the variables being
+/// reproducible across processes.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub struct Checkpoint(Vec<u64>);
+
+impl Display for Checkpoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0.iter().map(|w| format!("{:x}", w)).collect::<Vec<_>>().join("."))
+    }
+}
+
+impl FromStr for Checkpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.split('.')
+            .map(|w| u64::from_str_radix(w, 16).map_err(|_| String::from("Malformed checkpoint.")))
+            .collect::<Result<_, _>>()
+            .map(Checkpoint)
+    }
+}
+
+// Sequential encoder of the enumeration state.
+pub(crate) struct Writer(Vec<u64>);
+
+impl Writer {
+    pub(crate) fn new(fingerprint: u64) -> Self {
+        Self(vec![VERSION, fingerprint])
+    }
+
+    pub(crate) fn finish(self) -> Checkpoint {
+        Checkpoint(self.0)
+    }
+
+    pub(crate) fn word(&mut self, word: usize) {
+        self.0.push(word as u64);
+    }
+
+    pub(crate) fn variable(&mut self, variable: &Variable) {
+        let (tag, index) = match *variable {
+            Variable::Expr(i) => (0, i),
+            Variable::Global(i) => (1, i),
+            Variable::Local(i) => (2, i),
+        };
+        self.word(tag);
+        self.word(index);
+    }
+
+    pub(crate) fn constraint(&mut self, constraint: &Constraint) {
+        self.word(constraint.signature());
+        self.word(constraint.argument().len());
+        constraint.argument().iter().for_each(|v| self.variable(v));
+    }
+
+    pub(crate) fn constraints<'c>(
+        &mut self,
+        constraints: impl ExactSizeIterator<Item = &'c Constraint>,
+    ) {
+        self.word(constraints.len());
+        constraints.for_each(|c| self.constraint(c));
+    }
+}
+
+// Sequential decoder of the enumeration state.
+pub(crate) struct Reader<'c>(std::slice::Iter<'c, u64>);
+
+impl<'c> Reader<'c> {
+    pub(crate) fn new(checkpoint: &'c Checkpoint, fingerprint: u64) -> Result<Self, String> {
+        let mut reader = Self(checkpoint.0.iter());
+        (reader.word()? == VERSION as usize)
+            .then_some(())
+            .ok_or(String::from("Unsupported checkpoint version."))?;
+        (reader.0.next() == Some(&fingerprint))
+            .then_some(reader)
+            .ok_or(String::from("Checkpoint does not match the inputs."))
+    }
+
+    pub(crate) fn finish(mut self) -> Result<(), String> {
+        self.0.next().map_or(Ok(()), |_| Err(String::from("Malformed checkpoint.")))
+    }
+
+    pub(crate) fn word(&mut self) -> Result<usize, String> {
+        self.0
+            .next()
+            .and_then(|&w| usize::try_from(w).ok())
+            .ok_or(String::from("Malformed checkpoint."))
+    }
+
+    pub(crate) fn variable(&mut self) -> Result<Variable, String> {
+        match (self.word()?, self.word()?) {
+            (0, i) => Ok(Variable::Expr(i)),
+            (1, i) => Ok(Variable::Global(i)),
+            (2, i) => Ok(Variable::Local(i)),
+            _ => Err(String::from("Malformed checkpoint.")),
+        }
+    }
+
+    pub(crate) fn constraint(&mut self) -> Result<Constraint, String> {
+        let signature = self.word()?;
+        let length = self.word()?;
+        (0..length)
+            .map(|_| self.variable())
+            .collect::<Result<_, _>>()
+            .map(|argument| Constraint::new(signature, argument))
+    }
+
+    pub(crate) fn constraints(&mut self) -> Result<Vec<Constraint>, String> {
+        let length = self.word()?;
+        (0..length).map(|_| self.constraint()).collect()
+    }
+}
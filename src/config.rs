@@ -0,0 +1,116 @@
+/// # The group ordering enum.
+/// Constraints are matched group by group, where each group consists of the
+/// constraints with the same identifier and argument types. The order of the
+/// groups does not change the set of permutations, but it may change the
+/// order they are found in and the time it takes to find them.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum GroupOrder {
+    /// Match the groups in the order of their first source constraints.
+    Input,
+    /// Match the groups with fewer constraints first.
+    Smallest,
+    /// Match the smallest group first, then repeatedly the group sharing the
+    /// most local variables with the groups already placed, breaking ties by
+    /// the number of constraints.
+    #[default]
+    Connected,
+}
+
+/// # The search strategy enum.
+/// The strategy decides how much is checked after each binding is committed.
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq)]
+pub enum Strategy {
+    /// Only check the bindings of the matched constraints, and find the
+    /// conflicts they lead to by matching the rest.
+    #[default]
+    GenerateAndTest,
+    /// Also keep the domain of target locals that each source local of the
+    /// current group may be bound to, and narrow the domains down by the
+    /// target constraints that can still match the source constraints. A
+    /// binding is undone as soon as some source constraint of the group has
+    /// no matching target constraint left.
+    ForwardChecking,
+}
+
+/// # The configuration struct.
+/// A configuration tunes how the permutations are searched. The default
+/// configuration is used by `Isoperm::new`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Config {
+    pub(crate) order: GroupOrder,
+    pub(crate) refinement: usize,
+    pub(crate) strategy: Strategy,
+    pub(crate) backjumping: bool,
+    pub(crate) nogoods: usize,
+    pub(crate) renaming: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            order: GroupOrder::default(),
+            refinement: 32,
+            strategy: Strategy::default(),
+            backjumping: true,
+            nogoods: 0,
+            renaming: false,
+        }
+    }
+}
+
+impl Config {
+    /// Create the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the order in which constraint groups are matched.
+    pub fn order(mut self, order: GroupOrder) -> Self {
+        self.order = order;
+        self
+    }
+
+    /// Set the maximum number of rounds of colour refinement before the
+    /// search, which tells apart the local variables by their roles in the
+    /// constraints. Locals are only matched to locals of the same colour, and
+    /// the search is skipped if the colours are not balanced. The refinement
+    /// is disabled with zero rounds.
+    pub fn refinement(mut self, rounds: usize) -> Self {
+        self.refinement = rounds;
+        self
+    }
+
+    /// Set the strategy used to check the bindings during the search.
+    pub fn strategy(mut self, strategy: Strategy) -> Self {
+        self.strategy = strategy;
+        self
+    }
+
+    /// Enable or disable backjumping. When a group fails without finding any
+    /// permutation, the search steps back straight to the latest group that
+    /// bound its variables, or caused the failures under it. Backjumping is
+    /// enabled by default, and does not change the order of permutations.
+    pub fn backjumping(mut self, enabled: bool) -> Self {
+        self.backjumping = enabled;
+        self
+    }
+
+    /// Remember up to the given number of bindings under which some group
+    /// fails, so that the group is skipped when they are made again. This
+    /// only takes effect with backjumping, and is disabled with zero.
+    pub fn nogoods(mut self, capacity: usize) -> Self {
+        self.nogoods = capacity;
+        self
+    }
+
+    /// Enable or disable the renaming of global variables. When enabled, the
+    /// global variables are permuted as a class of their own, where each is
+    /// mapped to a global variable of the same type instead of to itself, and
+    /// the globals of each type must be as many on both sides. Other features
+    /// then treat them as they treat locals. `Isoperm::split` tells apart the
+    /// mappings of the locals and of the globals.
+    pub fn renaming(mut self, enabled: bool) -> Self {
+        self.renaming = enabled;
+        self
+    }
+}
@@ -0,0 +1,182 @@
+use crate::align::Statement;
+use crate::search::{Interrupt, Limit};
+use crate::statement::fingerprint;
+use crate::wrapper::{Builder, Var};
+use bimap::BiMap;
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::Hash;
+
+/// The signature of a constraint with the types of its arguments.
+pub type Signature<R, T> = (R, Vec<T>);
+
+/// # The discrepancy struct.
+/// A discrepancy is a key that is counted differently on the two sides.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Discrepancy<K> {
+    /// The key counted.
+    pub key: K,
+    /// The count on the source side.
+    pub source: usize,
+    /// The count on the target side.
+    pub target: usize,
+}
+
+/// # The diff struct.
+/// A diff tells how two bags differ, under the largest alignment found by
+/// `Builder::align`. The bags have a permutation if and only if the diff is
+/// empty and the search is not cut short. The fields are meant to be read by
+/// programs, and the `Display` implementation lists them one per line.
+pub struct Diff<R, T, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The source constraints left unmatched.
+    pub removed: Vec<Statement<R, U, V, W>>,
+    /// The target constraints left unmatched.
+    pub added: Vec<Statement<R, U, V, W>>,
+    /// The mapping of the local variables used by the matched constraints,
+    /// and of the other local variables paired by their types.
+    pub mapping: BiMap<Var<U, V, W>, Var<U, V, W>>,
+    /// The source local variables left unpaired, which are more than the
+    /// target local variables of their types left by the alignment.
+    pub unpaired_source: Vec<Var<U, V, W>>,
+    /// The target local variables left unpaired.
+    pub unpaired_target: Vec<Var<U, V, W>>,
+    /// The types with different numbers of local variables.
+    pub types: Vec<Discrepancy<T>>,
+    /// The signatures with different numbers of constraints.
+    pub groups: Vec<Discrepancy<Signature<R, T>>>,
+    /// The reason why the alignment was cut short, or `None` if it is the
+    /// largest.
+    pub interrupted: Option<Interrupt>,
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Clone + Eq + Hash,
+    T: Clone + Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    /// Compare the inputs, which need not have any permutation. The alignment
+    /// is bounded by the limit as in `align`. Returns an error if the inputs
+    /// are rejected by `align`.
+    pub fn diff(self, limit: Limit) -> Result<Diff<R, T, U, V, W>, String> {
+        let (source_constraints, source_variables) = self.source.clone();
+        let (target_constraints, target_variables) = self.target.clone();
+        // The inputs are validated by the alignment first.
+        let alignment = self.align(limit)?;
+        let local = |variables: &HashMap<Var<U, V, W>, T>| {
+            let local = variables.iter().filter(|(v, _)| matches!(v, Var::Local(_)));
+            local.map(|(_, t)| t.clone()).collect_vec()
+        };
+        let types = count(local(&source_variables), local(&target_variables));
+        let signature = |constraints: &[Statement<R, U, V, W>], variables: &HashMap<_, T>| {
+            constraints
+                .iter()
+                .map(|(r, vs)| (r.clone(), vs.iter().map(|v| variables[v].clone()).collect()))
+                .collect_vec()
+        };
+        let groups = count(
+            signature(&source_constraints, &source_variables),
+            signature(&target_constraints, &target_variables),
+        );
+        // Pair the locals left by the alignment by their types, in order.
+        let mut mapping = alignment.mapping;
+        let mut unpaired_target = alignment.target.locals;
+        let unpaired_source = alignment
+            .source
+            .locals
+            .into_iter()
+            .filter(|v| {
+                let paired = unpaired_target
+                    .iter()
+                    .position(|u| target_variables[u] == source_variables[v])
+                    .map(|i| unpaired_target.remove(i));
+                paired.map(|u| mapping.insert(v.clone(), u)).is_none()
+            })
+            .collect();
+        Ok(Diff {
+            removed: alignment.source.constraints,
+            added: alignment.target.constraints,
+            mapping,
+            unpaired_source,
+            unpaired_target,
+            types,
+            groups,
+            interrupted: alignment.interrupted,
+        })
+    }
+}
+
+impl<R, T, U, V, W> Diff<R, T, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Check if the bags do not differ.
+    pub fn is_empty(&self) -> bool {
+        self.removed.is_empty()
+            && self.added.is_empty()
+            && self.unpaired_source.is_empty()
+            && self.unpaired_target.is_empty()
+            && self.types.is_empty()
+            && self.groups.is_empty()
+    }
+}
+
+impl<R, T, U, V, W> Display for Diff<R, T, U, V, W>
+where
+    R: Debug,
+    T: Debug,
+    U: Debug + Eq + Hash + PartialEq,
+    V: Debug + Eq + Hash + PartialEq,
+    W: Debug + Eq + Hash + PartialEq,
+{
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for discrepancy in &self.types {
+            let Discrepancy { key, source, target } = discrepancy;
+            writeln!(f, "type {:?}: {} source locals, {} target locals", key, source, target)?;
+        }
+        for discrepancy in &self.groups {
+            let Discrepancy { key: (r, types), source, target } = discrepancy;
+            writeln!(f, "group {:?}{:?}: {} source, {} target", r, types, source, target)?;
+        }
+        for (r, vs) in &self.removed {
+            writeln!(f, "- {:?}{:?}", r, vs)?;
+        }
+        for (r, vs) in &self.added {
+            writeln!(f, "+ {:?}{:?}", r, vs)?;
+        }
+        for v in &self.unpaired_source {
+            writeln!(f, "unpaired source local {:?}", v)?;
+        }
+        for v in &self.unpaired_target {
+            writeln!(f, "unpaired target local {:?}", v)?;
+        }
+        if let Some(interrupt) = self.interrupted {
+            writeln!(f, "alignment cut short: {:?}", interrupt)?;
+        }
+        Ok(())
+    }
+}
+
+// Count the keys on both sides, and returns those counted differently in the
+// order of their fingerprints.
+fn count<K: Eq + Hash>(source: Vec<K>, target: Vec<K>) -> Vec<Discrepancy<K>> {
+    let mut counts: HashMap<K, (usize, usize)> = HashMap::new();
+    source.into_iter().for_each(|key| counts.entry(key).or_default().0 += 1);
+    target.into_iter().for_each(|key| counts.entry(key).or_default().1 += 1);
+    counts
+        .into_iter()
+        .filter(|(_, (source, target))| source != target)
+        .sorted_by_cached_key(|(key, _)| fingerprint(key))
+        .map(|(key, (source, target))| Discrepancy { key, source, target })
+        .collect()
+}
@@ -1,56 +1,118 @@
-use std::collections::{HashMap, HashSet};
+use std::cmp::Reverse;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::hash::Hash;
+use std::iter::zip;
+use std::sync::Arc;
+use std::time::Instant;
 
-use bimap::BiMap;
-use itertools::{zip, Itertools};
+use itertools::Itertools;
 
-use crate::statement::{group_constraints, Constraint, Variable};
+use crate::checkpoint::{Checkpoint, Reader, Writer};
+use crate::config::{Config, Strategy};
+use crate::environment::Environment;
+use crate::forbid::Forbidden;
+use crate::refine::refine;
+use crate::search::{Context, Interrupt};
+use crate::statement::{fingerprint, group_constraints, order_groups, Constraint, Variable};
+
+// The stages matched in each group before a subtree of the search, where all
+// groups but the last are fully matched.
+pub(crate) type Prefix = Vec<Vec<(usize, usize)>>;
 
 #[derive(Clone, Debug)]
 pub(crate) struct StatementEnumerator {
-    environment: BiMap<Variable, Variable>,
+    fingerprint: u64,
+    initial: Environment,
+    environment: Environment,
     local: Vec<(Vec<Variable>, Vec<Variable>)>,
     group: Vec<GroupEnumerator>,
     unconfined: Option<Vec<GroupEnumerator>>,
     stage: Option<usize>,
+    feasible: bool,
+    backjumping: bool,
+    conflict: Vec<Conflict>,
+    // The bindings under which each group is known to fail, and the number
+    // of bindings that can still be remembered.
+    nogood: Vec<Vec<Vec<(Variable, Variable)>>>,
+    capacity: usize,
+    forbidden: Arc<Forbidden>,
+}
+
+// The outcome of the search under a group since it was last started: whether
+// a permutation has been found, and the earlier groups that the failures
+// depend on.
+#[derive(Clone, Debug, Default)]
+struct Conflict {
+    succeeded: bool,
+    cause: BTreeSet<usize>,
 }
 
 impl StatementEnumerator {
     pub(crate) fn new<T: Eq + Hash>(
-        source_constraints: Vec<Constraint>,
+        mut source_constraints: Vec<Constraint>,
         source_variables: &HashMap<Variable, T>,
-        target_constraints: Vec<Constraint>,
+        mut target_constraints: Vec<Constraint>,
         target_variables: &HashMap<Variable, T>,
+        global: Vec<(Variable, Variable)>,
+        forbidden: (Vec<Constraint>, Vec<Constraint>),
+        config: &Config,
     ) -> Result<Self, String> {
-        // Assume that variables with the same name have the same type.
-        // Introduce all global variables to the environment.
-        let environment = source_variables
-            .iter()
-            .chain(target_variables.iter())
-            .filter_map(|(&v, _)| match v {
-                Variable::Global(_) => Some((v, v)),
-                _ => None,
-            })
-            .collect();
+        // Assume that variables are numbered from zero on each side.
+        let numbered = |variables: &HashMap<Variable, T>| {
+            variables.keys().copied().sorted_by_key(Variable::index).collect_vec()
+        };
+        let (source, target) = (numbered(source_variables), numbered(target_variables));
+        let mut environment = Environment::new(source.clone(), target.clone(), &global);
         // Collect local variables.
-        let local = Variable::group_local_by_type(source_variables)
+        let mut local: Vec<_> = Variable::group_local_by_type(source_variables)
             .into_iter()
             .chain(Variable::group_local_by_type(target_variables))
             .into_group_map()
-            .into_iter()
-            .map(|(_, v)| {
+            .into_values()
+            .map(|v| {
                 v.into_iter()
                     .collect_tuple()
                     .filter(|(source, target)| source.len() == target.len())
                     .ok_or(String::from("Local variable mismatch."))
             })
             .collect::<Result<_, _>>()?;
+        let forbidden =
+            Arc::new(Forbidden::new(forbidden, (&source_constraints, &target_constraints)));
+        // The unconfined locals of a type are matched by a group of their own,
+        // if some of them are used by forbidden constraints, so that the
+        // forbidden constraints are checked before they are counted free. The
+        // locals of a type which expressions may leave unbound are all matched
+        // by the group.
+        let (source_watched, target_watched) = forbidden.locals();
+        let used = |constraints: &[Constraint]| {
+            constraints.iter().flat_map(Constraint::argument).copied().collect::<HashSet<_>>()
+        };
+        let (source_used, target_used) = (used(&source_constraints), used(&target_constraints));
+        let expression = |v: &&Variable| matches!(v, Variable::Expr(_));
+        let blurred: HashSet<_> = source_used
+            .iter()
+            .filter(expression)
+            .map(|v| &source_variables[v])
+            .chain(target_used.iter().filter(expression).map(|u| &target_variables[u]))
+            .collect();
+        for (s, t) in &local {
+            let confined = !blurred.contains(&source_variables[&s[0]]);
+            let s = s.iter().filter(|v| !confined || !source_used.contains(v)).collect_vec();
+            let t = t.iter().filter(|u| !confined || !target_used.contains(u)).collect_vec();
+            let watched = s.iter().any(|v| source_watched.contains(v))
+                || t.iter().any(|u| target_watched.contains(u));
+            if watched && s.len() == t.len() {
+                let singleton = |v: &&Variable| Constraint::new(usize::MAX, vec![**v]);
+                source_constraints.extend(s.iter().map(singleton));
+                target_constraints.extend(t.iter().map(singleton));
+            }
+        }
         // Transform constraint groups to enumerators.
         let source_groups = group_constraints(source_constraints, source_variables)?;
         let target_groups = group_constraints(target_constraints, target_variables)?;
-        let group = source_groups
+        let mut group: Vec<_> = source_groups
             .into_iter()
-            .chain(target_groups.into_iter())
+            .chain(target_groups)
             .into_group_map()
             .into_iter()
             .map(|(k, v)| {
@@ -58,58 +120,360 @@ impl StatementEnumerator {
                     .collect_tuple()
                     .filter(|(source, target)| source.len() == target.len())
                     .ok_or(format!("Constraint {:?} mismatch.", k.0))
-                    .map(|(s, t)| GroupEnumerator::new(s, t))
             })
             .collect::<Result<_, _>>()?;
-        Ok(Self { environment, local, group, unconfined: None, stage: Some(0) })
+        // Fix the order of stages, so that checkpoints are portable.
+        local.sort();
+        group.sort();
+        let group = order_groups(config.order, group);
+        // Restrict the bindings by colours, where locals start with the colours
+        // of their types, paired globals with the colours of their pairs, and
+        // other variables with colours of their own.
+        let mut initial = (
+            (0..source.len()).map(|i| (2, i)).collect_vec(),
+            (0..target.len()).map(|i| (3, i)).collect_vec(),
+        );
+        local.iter().enumerate().for_each(|(k, (s, t))| {
+            s.iter().for_each(|v| initial.0[v.index()] = (0, k));
+            t.iter().for_each(|u| initial.1[u.index()] = (0, k));
+        });
+        global.iter().enumerate().for_each(|(k, (v, u))| {
+            initial.0[v.index()] = (1, k);
+            initial.1[u.index()] = (1, k);
+        });
+        let colouring = refine(&group, (&source, &target), initial, config.refinement);
+        let feasible = colouring.is_some() && !forbidden.violated_any(&environment);
+        colouring.into_iter().for_each(|colouring| environment.colour(colouring));
+        let fingerprint = fingerprint(&(&global, &local, &group, config.strategy, &forbidden));
+        let forward = config.strategy == Strategy::ForwardChecking;
+        let group = group
+            .into_iter()
+            .map(|(s, t)| GroupEnumerator::new(s, t, forward, &forbidden))
+            .collect_vec();
+        let length = group.len();
+        Ok(Self {
+            fingerprint,
+            initial: environment.clone(),
+            environment,
+            local,
+            group,
+            unconfined: None,
+            stage: feasible.then_some(0),
+            feasible,
+            backjumping: config.backjumping,
+            conflict: vec![Conflict::default(); length],
+            nogood: vec![Vec::new(); length],
+            capacity: config.nogoods,
+            forbidden,
+        })
+    }
+
+    // Returns the bindings of the last permutation found.
+    pub(crate) fn environment(&self) -> &Environment {
+        &self.environment
     }
 
-    // Match constraint groups if needed and check if they are matched.
-    fn advance_group(&mut self) -> bool {
+    // Snapshot the enumeration state. The environment is not saved, as it is
+    // rebuilt by replaying the stages of the groups.
+    pub(crate) fn checkpoint(&self) -> Checkpoint {
+        let mut writer = Writer::new(self.fingerprint);
+        writer.word(self.stage.map_or(0, |index| index + 1));
+        self.group.iter().for_each(|focus| focus.save(&mut writer));
+        writer.word(self.unconfined.as_ref().map_or(0, |free| free.len() + 1));
+        self.unconfined.iter().flatten().for_each(|focus| focus.save(&mut writer));
+        writer.finish()
+    }
+
+    // Restore the enumeration state from a snapshot of the same problem.
+    pub(crate) fn restore(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        let malformed = || String::from("Malformed checkpoint.");
+        let mut reader = Reader::new(checkpoint, self.fingerprint)?;
+        let stage = reader.word()?.checked_sub(1);
+        let mut environment = self.initial.clone();
+        let group = self
+            .group
+            .iter()
+            .map(|focus| {
+                GroupEnumerator::load(
+                    &mut reader,
+                    &mut environment,
+                    focus.forward,
+                    &self.forbidden,
+                )?
+                .filter(|loaded| loaded.source == focus.source)
+                .ok_or_else(malformed)
+            })
+            .collect::<Result<_, _>>()?;
+        let unconfined = reader
+            .word()?
+            .checked_sub(1)
+            .map(|length| {
+                (0..length)
+                    .map(|_| {
+                        GroupEnumerator::load(
+                            &mut reader,
+                            &mut environment,
+                            false,
+                            &self.forbidden,
+                        )?
+                        .ok_or_else(malformed)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+        reader.finish()?;
+        let limit = self.group.len() + unconfined.as_ref().map_or(0, Vec::len);
+        if stage.is_some_and(|index| index > limit) {
+            return Err(malformed());
+        }
+        self.environment = environment;
+        self.group = group;
+        self.unconfined = unconfined;
+        self.stage = stage;
+        // The permutations found before the checkpoint are unknown, so the
+        // groups are only stepped back from in order.
+        let succeeded = Conflict { succeeded: true, cause: BTreeSet::new() };
+        self.conflict.fill(succeeded);
+        Ok(())
+    }
+
+    // Start the enumeration over, keeping the failures remembered so far.
+    pub(crate) fn restart(&mut self) {
+        self.environment = self.initial.clone();
+        self.group.iter_mut().for_each(GroupEnumerator::clear);
+        self.unconfined = None;
+        self.stage = self.feasible.then_some(0);
+        self.conflict.fill(Conflict::default());
+    }
+
+    // Returns an enumeration from the start of the permutations that bind a
+    // source local to a target local, or `None` if they cannot be bound.
+    pub(crate) fn pinned(&self, v: &Variable, u: &Variable) -> Option<Self> {
+        let mut pinned = self.clone();
+        let allowed = pinned.initial.pin(v, u) && !self.forbidden.violated_any(&pinned.initial);
+        allowed.then(|| {
+            pinned.restart();
+            pinned
+        })
+    }
+
+    // Returns the prefixes of the search tree with the given number of stages,
+    // or fewer if every group is matched before, in the order they are
+    // searched from the start. Together, their subtrees cover the search tree.
+    pub(crate) fn split(&self, depth: usize) -> Vec<Prefix> {
+        let mut scratch = self.clone();
+        scratch.restart();
+        let mut prefixes = Vec::new();
+        if scratch.stage.is_none() {
+            return prefixes;
+        }
+        if depth == 0 || scratch.group.is_empty() {
+            prefixes.push(Vec::new());
+        } else if scratch.group[0].refresh(&scratch.environment) {
+            scratch.walk(0, depth, &mut vec![Vec::new()], &mut prefixes);
+        }
+        prefixes
+    }
+
+    // Collect the prefixes under the stages matched so far, by matching at
+    // most the given number of stages more.
+    fn walk(&mut self, index: usize, depth: usize, prefix: &mut Prefix, found: &mut Vec<Prefix>) {
+        let last = index + 1 == self.group.len();
+        let focus = &mut self.group[index];
+        let complete = focus.stage.len() == focus.source.len();
+        if depth == 0 || complete && last {
+            found.push(prefix.clone());
+        } else if complete {
+            if self.group[index + 1].refresh(&self.environment) {
+                prefix.push(Vec::new());
+                self.walk(index + 1, depth, prefix, found);
+                prefix.pop();
+            }
+        } else {
+            focus.open(&self.environment);
+            let (s, candidates) = focus.choices.pop().unwrap();
+            for t in candidates.into_iter().rev() {
+                if self.group[index].commit(&mut self.environment, s, t) {
+                    prefix[index].push((s, t));
+                    self.walk(index, depth - 1, prefix, found);
+                    prefix[index].pop();
+                    self.group[index].undo(&mut self.environment);
+                }
+            }
+        }
+    }
+
+    // Returns an enumerator of the permutations in the subtree under a prefix
+    // returned by `split`, in the order they are found from the start. The
+    // groups of the prefix are never advanced past their stages.
+    pub(crate) fn subtree(&self, prefix: &Prefix) -> Self {
+        let mut subtree = self.clone();
+        subtree.restart();
+        for (focus, stages) in zip(&mut subtree.group, prefix) {
+            focus.refresh(&subtree.environment);
+            stages.iter().for_each(|&(s, t)| {
+                focus.commit(&mut subtree.environment, s, t);
+            });
+            // Only the last level is kept, so that the group runs out once it
+            // is searched.
+            focus.open(&subtree.environment);
+        }
+        if let Some(index) = prefix.len().checked_sub(1) {
+            let focus = &subtree.group[index];
+            let complete = focus.stage.len() == focus.source.len();
+            subtree.stage = subtree.stage.map(|_| index + complete as usize);
+        }
+        subtree
+    }
+
+    // Match constraint groups if needed and check if they are matched. The
+    // stage is kept up to date, so that an interrupted search can be resumed.
+    fn advance_group(&mut self, context: &mut Context) -> Result<bool, Interrupt> {
         if let Some(mut index) = self.stage {
-            while let Some(focus) = self.group.get_mut(index) {
-                if focus.advance(&mut self.environment) {
+            while index < self.group.len() {
+                self.stage = Some(index);
+                context.check()?;
+                context.enter(index);
+                let known =
+                    self.group[index].fresh().then(|| self.recall(index, context)).flatten();
+                if known.is_none() && self.group[index].advance(&mut self.environment, context)? {
                     index += 1;
-                } else if index == 0 {
-                    self.stage = None;
-                    return false;
+                } else if let Some(previous) = self.retreat(index, known, context) {
+                    index = previous;
                 } else {
-                    focus.reset(&mut self.environment);
-                    index -= 1;
+                    self.stage = None;
+                    return Ok(false);
                 }
             }
+            self.conflict.iter_mut().for_each(|conflict| conflict.succeeded = true);
             self.stage = Some(index);
-            true
+            Ok(true)
         } else {
-            false
+            Ok(false)
         }
     }
 
+    // Step back from a group without more bindings to the latest group that
+    // can change the outcome, and reset the groups in between. A group that
+    // failed without finding any permutation only depends on the groups that
+    // bound its variables, and on the causes of the failures under it. Returns
+    // `None` if no earlier group can change the outcome.
+    fn retreat(
+        &mut self,
+        index: usize,
+        known: Option<BTreeSet<usize>>,
+        context: &mut Context,
+    ) -> Option<usize> {
+        let conflict = std::mem::take(&mut self.conflict[index]);
+        let previous = if !self.backjumping || conflict.succeeded {
+            index.checked_sub(1)
+        } else {
+            let mut cause = conflict.cause;
+            let recorded = known.is_some();
+            cause.extend(known.unwrap_or_default());
+            cause.extend(self.bound_by(index));
+            if !recorded {
+                self.remember(index, &cause);
+            }
+            let previous = cause.pop_last();
+            if let Some(previous) = previous {
+                self.conflict[previous].cause.extend(cause);
+            }
+            if previous != index.checked_sub(1) {
+                context.record(|statistics| statistics.backjumps += 1);
+            }
+            previous
+        };
+        (previous.map_or(0, |previous| previous + 1)..=index).rev().for_each(|skipped| {
+            self.group[skipped].reset(&mut self.environment);
+            self.conflict[skipped] = Conflict::default();
+        });
+        previous
+    }
+
+    // Returns the earlier group that made the binding at the position on the
+    // trail.
+    fn owner(&self, index: usize, position: usize) -> Option<usize> {
+        (0..index).rev().find(|&j| self.group[j].first_mark().is_some_and(|mark| mark <= position))
+    }
+
+    // Returns the earlier groups that bound the variables of a group.
+    fn bound_by(&self, index: usize) -> BTreeSet<usize> {
+        let (source, target) = &self.group[index].scope;
+        let position = source.iter().filter_map(|v| self.environment.position(v)).chain(
+            target.iter().filter_map(|u| {
+                self.environment.source_of(u).and_then(|v| self.environment.position(&v))
+            }),
+        );
+        position.filter_map(|position| self.owner(index, position)).collect()
+    }
+
+    // Remember the bindings of the groups that a failure of a group depends on.
+    fn remember(&mut self, index: usize, cause: &BTreeSet<usize>) {
+        if self.capacity == 0 {
+            return;
+        }
+        let end = |j: usize| {
+            self.group[j + 1..index]
+                .iter()
+                .find_map(GroupEnumerator::first_mark)
+                .unwrap_or(self.environment.mark())
+        };
+        let binding = cause
+            .iter()
+            .filter_map(|&j| self.group[j].first_mark().map(|start| (start, end(j))))
+            .flat_map(|(start, end)| self.environment.bound_in(start..end))
+            .sorted()
+            .collect();
+        self.capacity -= 1;
+        self.nogood[index].push(binding);
+    }
+
+    // Check if a group is known to fail under the current bindings, and
+    // returns the groups that made the bindings.
+    fn recall(&self, index: usize, context: &mut Context) -> Option<BTreeSet<usize>> {
+        let binding = self.nogood[index].iter().find(|binding| {
+            binding.iter().all(|(v, u)| self.environment.target_of(v) == Some(*u))
+        })?;
+        context.record(|statistics| statistics.nogoods += 1);
+        let position = binding.iter().filter_map(|(v, _)| self.environment.position(v));
+        Some(position.filter_map(|position| self.owner(index, position)).collect())
+    }
+
+    // Returns the source and target locals of each type left unbound by the
+    // constraint groups.
+    pub(crate) fn free(&self) -> impl Iterator<Item = (Vec<Variable>, Vec<Variable>)> + '_ {
+        self.local.iter().filter_map(|(s, t)| {
+            let source_remaining =
+                s.iter().copied().filter(|v| self.environment.target_of(v).is_none()).collect_vec();
+            let target_remaining =
+                t.iter().copied().filter(|v| self.environment.source_of(v).is_none()).collect_vec();
+            (!source_remaining.is_empty() && !target_remaining.is_empty())
+                .then_some((source_remaining, target_remaining))
+        })
+    }
+
+    // Returns the groups of singleton constraints of the unconfined variables.
+    fn unconfined_groups(&self) -> Vec<GroupEnumerator> {
+        let singleton = |v| Constraint::new(0, vec![v]);
+        self.free()
+            .map(|(s, t)| {
+                GroupEnumerator::new(
+                    s.into_iter().map(singleton).collect(),
+                    t.into_iter().map(singleton).collect(),
+                    false,
+                    &self.forbidden,
+                )
+            })
+            .collect()
+    }
+
     // Generate unconfined groups if needed, and check if there is one.
     fn generate_unconfined(&mut self) -> bool {
         match (self.stage, &self.unconfined) {
             (_, Some(_)) => true,
             (Some(index), None) if index == self.group.len() => {
-                self.unconfined = Some(
-                    self.local
-                        .clone()
-                        .into_iter()
-                        .filter_map(|(s, t)| {
-                            let source_remaining = s
-                                .into_iter()
-                                .filter(|v| !self.environment.contains_right(v))
-                                .map(|v| Constraint::new(0, vec![v]))
-                                .collect_vec();
-                            let target_remaining = t
-                                .into_iter()
-                                .filter(|v| !self.environment.contains_left(v))
-                                .map(|v| Constraint::new(0, vec![v]))
-                                .collect_vec();
-                            (!source_remaining.is_empty() && !target_remaining.is_empty())
-                                .then(|| GroupEnumerator::new(source_remaining, target_remaining))
-                        })
-                        .collect(),
-                );
+                self.unconfined = Some(self.unconfined_groups());
                 true
             }
             _ => false,
@@ -117,123 +481,858 @@ impl StatementEnumerator {
     }
 
     // Match unconfined variables if needed and check if they are matched.
-    fn advance_unconfined(&mut self) -> bool {
+    fn advance_unconfined(&mut self, context: &mut Context) -> Result<bool, Interrupt> {
         if let (Some(mut index), Some(free)) = (self.stage, &mut self.unconfined) {
             while let Some(focus) = free.get_mut(index - self.group.len()) {
-                if focus.advance(&mut self.environment) {
+                self.stage = Some(index);
+                context.check()?;
+                context.enter(index);
+                if focus.advance(&mut self.environment, context)? {
                     index += 1;
                 } else if index == self.group.len() {
-                    self.stage = (index > 0).then(|| index - 1);
+                    self.stage = index.checked_sub(1);
                     self.unconfined = None;
-                    return false;
+                    return Ok(false);
                 } else {
                     focus.reset(&mut self.environment);
                     index -= 1;
                 }
             }
+            // Without unconfined variables, the groups are advanced on the next
+            // call, and their next permutation may leave other locals unbound.
+            if index == self.group.len() {
+                self.unconfined = None;
+            }
             if index == 0 {
                 self.stage = None;
             } else {
                 self.stage = Some(index - 1);
             }
-            true
+            Ok(true)
         } else {
-            false
+            Ok(false)
         }
     }
-}
 
-impl Iterator for StatementEnumerator {
-    type Item = BiMap<Variable, Variable>;
+    // Find the next permutation of the locals bound by the constraint groups,
+    // leaving the unconfined variables free, unless the search is interrupted.
+    pub(crate) fn next_core(&mut self, context: &mut Context) -> Result<bool, Interrupt> {
+        // Drop the bindings of unconfined variables from a full permutation.
+        if let Some(mut free) = self.unconfined.take() {
+            free.iter_mut().rev().for_each(|focus| focus.reset(&mut self.environment));
+            self.stage = self.stage.and(self.group.len().checked_sub(1));
+        }
+        let start = Instant::now();
+        let grouped = self.advance_group(context);
+        context.record(|statistics| statistics.group_time += start.elapsed());
+        if grouped? {
+            // Advance the last group on the next call.
+            self.stage = self.group.len().checked_sub(1);
+            return Ok(true);
+        }
+        Ok(false)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
+    // Find the next permutation and keep it in the environment, unless the
+    // search is interrupted. Returns `false` if there are no more permutations.
+    pub(crate) fn next(&mut self, context: &mut Context) -> Result<bool, Interrupt> {
         while self.stage.is_some() {
-            if self.advance_group() && self.generate_unconfined() && self.advance_unconfined() {
-                return Some(self.environment.clone());
+            let start = Instant::now();
+            let grouped = self.advance_group(context);
+            context.record(|statistics| statistics.group_time += start.elapsed());
+            if grouped? && self.generate_unconfined() {
+                let start = Instant::now();
+                let unconfined = self.advance_unconfined(context);
+                context.record(|statistics| statistics.unconfined_time += start.elapsed());
+                if unconfined? {
+                    return Ok(true);
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    // Skip permutations and keep the next one as in `next`. The count is
+    // decreased as permutations are skipped, so that an interrupted search can
+    // be resumed. The orderings of unconfined variables are skipped at once,
+    // and so are whole permutations of the constraint groups, which all have
+    // the same number of orderings.
+    pub(crate) fn skip(
+        &mut self,
+        skip: &mut usize,
+        context: &mut Context,
+    ) -> Result<bool, Interrupt> {
+        while *skip > 0 {
+            let settled = self.stage
+                == (self.group.len() + self.unconfined.iter().flatten().count()).checked_sub(1);
+            match &self.unconfined {
+                Some(free) if settled && free.iter().all(GroupEnumerator::complete) => {
+                    let (mut digits, radices) = self.ordering();
+                    let carry = increment(&mut digits, &radices, *skip as u128);
+                    if carry == 0 {
+                        self.order(&digits);
+                        *skip = 0;
+                        break;
+                    }
+                    // Count from the first ordering of the next permutation of
+                    // the groups, which is no more than the count.
+                    let whole = (carry - 1).saturating_mul(product(&radices));
+                    *skip = (whole + value(&digits, &radices)) as usize + 1;
+                }
+                Some(_) if self.stage.is_some_and(|index| index >= self.group.len()) => {
+                    // Finish the ordering being searched.
+                    if !self.next(context)? {
+                        return Ok(false);
+                    }
+                    *skip -= 1;
+                    continue;
+                }
+                _ => (),
+            }
+            if !self.next_core(context)? {
+                return Ok(false);
+            }
+            self.stage = Some(self.group.len());
+            self.generate_unconfined();
+            self.order(&vec![0; self.ordering().1.len()]);
+            *skip -= 1;
+        }
+        self.next(context)
+    }
+
+    // Walk down a random path of the search tree of the constraint groups from
+    // the start, choosing among the candidates that match by `choose`, which
+    // returns a number below its argument. Returns the product of the numbers
+    // of candidates that match along the path, or `None` if the path ends
+    // before every group is matched. The bindings of the path are kept in the
+    // environment until the enumeration is restarted.
+    pub(crate) fn probe(&mut self, choose: &mut dyn FnMut(usize) -> usize) -> Option<f64> {
+        self.restart();
+        self.stage?;
+        self.stage = None;
+        let mut weight = 1.0;
+        for focus in &mut self.group {
+            if !focus.refresh(&self.environment) {
+                return None;
+            }
+            while !focus.complete() {
+                focus.open(&self.environment);
+                let (s, candidates) = focus.choices.pop().unwrap();
+                let matching = candidates
+                    .into_iter()
+                    .rev()
+                    .filter(|&t| {
+                        let matched = focus.commit(&mut self.environment, s, t);
+                        if matched {
+                            focus.undo(&mut self.environment);
+                        }
+                        matched
+                    })
+                    .collect_vec();
+                if matching.is_empty() {
+                    return None;
+                }
+                weight *= matching.len() as f64;
+                focus.commit(&mut self.environment, s, matching[choose(matching.len())]);
+            }
+        }
+        Some(weight)
+    }
+
+    // Find the permutations of the lowest costs, where the cost of a subtree
+    // is bounded below by the costs of its bindings, plus the lowest cost of
+    // each unbound source local, as in `minimize`.
+    pub(crate) fn optimize(
+        &self,
+        top: usize,
+        cost: &mut dyn FnMut(Variable, Variable) -> f64,
+        context: &mut Context,
+    ) -> (Vec<Scored>, Option<Interrupt>) {
+        let local = |variables: &[Variable]| {
+            variables.iter().copied().filter(|v| matches!(v, Variable::Local(_))).collect_vec()
+        };
+        let (source, target) =
+            self.local.iter().fold((Vec::new(), Vec::new()), |mut all, (s, t)| {
+                all.0.extend(local(s));
+                all.1.extend(local(t));
+                all
+            });
+        let mut lowest = vec![0.0; self.initial.source_count()];
+        let mut remaining = 0.0;
+        for v in source {
+            let costs = target.iter().filter(|u| self.initial.compatible(&v, u));
+            lowest[v.index()] = costs.map(|&u| cost(v, u)).fold(f64::INFINITY, f64::min);
+            remaining += lowest[v.index()];
+        }
+        let mut costs = Costs { cost, lowest, partial: 0.0, remaining, saved: Vec::new() };
+        self.minimize(top, &mut costs, context)
+    }
+
+    // Find the permutations of the lowest scores, searching the groups and then
+    // the unconfined variables from the start as in `next`, where a subtree is
+    // pruned if its bound cannot be among the lowest scores found so far.
+    // Returns the lowest scores found, in order, with the bindings of locals,
+    // and the reason why the search was cut short, if it was.
+    pub(crate) fn minimize(
+        &self,
+        top: usize,
+        objective: &mut dyn Objective,
+        context: &mut Context,
+    ) -> (Vec<Scored>, Option<Interrupt>) {
+        let mut scratch = self.clone();
+        scratch.restart();
+        let mut search = Optimization { top, objective, best: Vec::new() };
+        let mut group = std::mem::take(&mut scratch.group);
+        let length = group.len();
+        let interrupt = match scratch.stage {
+            Some(_) => search.enter(&mut scratch, &mut group, length, 0, context).err(),
+            None => None,
+        };
+        (search.best, interrupt)
+    }
+
+    // Returns the orderings of the unconfined variables as digits, and the
+    // radices of the digits.
+    fn ordering(&self) -> (Vec<usize>, Vec<usize>) {
+        self.unconfined
+            .iter()
+            .flatten()
+            .flat_map(|focus| {
+                let length = focus.source.len();
+                (0..length).map(move |i| {
+                    let remaining =
+                        focus.choices.get(i).map_or(0, |(_, candidates)| candidates.len());
+                    (length - i - 1 - remaining.min(length - i - 1), length - i)
+                })
+            })
+            .unzip()
+    }
+
+    // Bind the unconfined variables by the ordering of the digits, as if it was
+    // found by `next`.
+    fn order(&mut self, digits: &[usize]) {
+        let free = self.unconfined.as_mut().unwrap();
+        free.iter_mut().rev().for_each(|focus| focus.reset(&mut self.environment));
+        let mut digits = digits.iter().copied();
+        free.iter_mut().for_each(|focus| {
+            let length = focus.source.len();
+            focus.seek(&mut self.environment, digits.by_ref().take(length));
+        });
+        let index = self.group.len() + free.len();
+        self.stage = index.checked_sub(1);
+    }
+}
+
+// A score with the bindings of locals of a permutation.
+pub(crate) type Scored = (f64, Vec<(Variable, Variable)>);
+
+// The scores of the permutations, bounded below during the search.
+pub(crate) trait Objective {
+    // Account for the source variables bound since the last call, and returns
+    // a lower bound of the scores of the permutations under the bindings.
+    fn bind(&mut self, environment: &Environment, bound: &[Variable]) -> f64;
+
+    // Drop the bindings accounted for by the last call of `bind`.
+    fn unbind(&mut self);
+
+    // Returns the score of the permutation of the bindings.
+    fn score(&self, environment: &Environment) -> f64;
+}
+
+// The sum of the costs of the pairs of locals of a permutation.
+struct Costs<'c> {
+    cost: &'c mut dyn FnMut(Variable, Variable) -> f64,
+    // The lowest cost of each source local.
+    lowest: Vec<f64>,
+    // The costs of the bindings, and the lowest costs of the unbound locals.
+    partial: f64,
+    remaining: f64,
+    saved: Vec<(f64, f64)>,
+}
+
+impl Objective for Costs<'_> {
+    fn bind(&mut self, environment: &Environment, bound: &[Variable]) -> f64 {
+        self.saved.push((self.partial, self.remaining));
+        for v in bound.iter().filter(|v| matches!(v, Variable::Local(_))) {
+            let u = environment.target_of(v).unwrap();
+            self.partial += (self.cost)(*v, u);
+            self.remaining -= self.lowest[v.index()];
+        }
+        self.partial + self.remaining
+    }
+
+    fn unbind(&mut self) {
+        (self.partial, self.remaining) = self.saved.pop().unwrap();
+    }
+
+    fn score(&self, _: &Environment) -> f64 {
+        self.partial
+    }
+}
+
+// The state of the search for the permutations of the lowest scores.
+struct Optimization<'c> {
+    top: usize,
+    objective: &'c mut dyn Objective,
+    best: Vec<Scored>,
+}
+
+impl Optimization<'_> {
+    // Search the stages of a group and the groups after it, where the groups
+    // up to the given length are constraint groups.
+    fn descend(
+        &mut self,
+        perm: &mut StatementEnumerator,
+        group: &mut Vec<GroupEnumerator>,
+        length: usize,
+        index: usize,
+        context: &mut Context,
+    ) -> Result<(), Interrupt> {
+        context.tick()?;
+        if index == group.len() {
+            if index == length {
+                // Search the unconfined variables left by the groups.
+                group.extend(perm.unconfined_groups());
+                if group.len() > length {
+                    let searched = self.enter(perm, group, length, index, context);
+                    group.truncate(length);
+                    return searched;
+                }
             }
+            let score = self.objective.score(&perm.environment);
+            let position = self.best.partition_point(|(best, _)| *best <= score);
+            self.best.insert(position, (score, perm.environment.pairs().collect()));
+            self.best.truncate(self.top);
+            return Ok(());
+        }
+        let focus = &mut group[index];
+        if focus.complete() {
+            return self.enter(perm, group, length, index + 1, context);
         }
-        None
+        focus.open(&perm.environment);
+        let (s, candidates) = focus.choices.pop().unwrap();
+        for t in candidates.into_iter().rev() {
+            let mark = perm.environment.mark();
+            if !group[index].commit(&mut perm.environment, s, t) {
+                continue;
+            }
+            context.record(|statistics| statistics.nodes += 1);
+            let bound = perm.environment.bound_since(mark).collect_vec();
+            let bound = self.objective.bind(&perm.environment, &bound);
+            let searched = if self.pruned(bound) {
+                Ok(())
+            } else {
+                self.descend(perm, group, length, index, context)
+            };
+            self.objective.unbind();
+            group[index].undo(&mut perm.environment);
+            searched?;
+        }
+        Ok(())
+    }
+
+    // Start searching a group, unless it has no match.
+    fn enter(
+        &mut self,
+        perm: &mut StatementEnumerator,
+        group: &mut Vec<GroupEnumerator>,
+        length: usize,
+        index: usize,
+        context: &mut Context,
+    ) -> Result<(), Interrupt> {
+        if group.get_mut(index).is_some_and(|focus| !focus.refresh(&perm.environment)) {
+            return Ok(());
+        }
+        self.descend(perm, group, length, index, context)
     }
+
+    // Check if the lowest score of the subtree cannot be among the lowest found.
+    fn pruned(&self, bound: f64) -> bool {
+        self.best.len() == self.top && self.best.last().is_some_and(|(worst, _)| bound >= *worst)
+    }
+}
+
+// Add to a number of mixed radices in place, and returns the carry.
+fn increment(digits: &mut [usize], radices: &[usize], amount: u128) -> u128 {
+    zip(digits, radices).rev().fold(amount, |carry, (digit, &radix)| {
+        let total = *digit as u128 + carry;
+        *digit = (total % radix as u128) as usize;
+        total / radix as u128
+    })
 }
 
+// Returns the value of a number of mixed radices, saturated.
+fn value(digits: &[usize], radices: &[usize]) -> u128 {
+    zip(digits, radices).fold(0u128, |value, (&digit, &radix)| {
+        value.saturating_mul(radix as u128).saturating_add(digit as u128)
+    })
+}
+
+// Returns the product of radices, saturated.
+fn product(radices: &[usize]) -> u128 {
+    radices.iter().fold(1u128, |product, &radix| product.saturating_mul(radix as u128))
+}
+
+// Find the previous and the next identical constraints of each constraint.
+fn twins(constraints: &[Constraint]) -> (Vec<Option<usize>>, Vec<Option<usize>>) {
+    let mut last = HashMap::new();
+    let previous = constraints.iter().enumerate().map(|(i, c)| last.insert(c, i)).collect_vec();
+    let mut next = vec![None; constraints.len()];
+    previous.iter().enumerate().for_each(|(i, p)| p.iter().for_each(|&p| next[p] = Some(i)));
+    (previous, next)
+}
+
+// Returns the variables used by the constraints, except expressions.
+fn scope(constraints: &[Constraint]) -> Vec<Variable> {
+    let variables = constraints.iter().flat_map(|c| c.argument()).copied();
+    variables.filter(|v| !matches!(v, Variable::Expr(_))).unique().collect()
+}
+
+// The order to choose source constraints in: the fewest estimated candidates,
+// then the most bound arguments, then the first in the group.
+type Priority = (usize, Reverse<usize>, usize);
+
 #[derive(Clone, Debug)]
 struct GroupEnumerator {
-    choices: Vec<Vec<Constraint>>,
-    stage: Vec<(Constraint, BiMap<Variable, Variable>)>,
-    target: HashSet<Constraint>,
+    // The chosen source constraint and its remaining target candidates at
+    // each level.
+    choices: Vec<(usize, Vec<usize>)>,
+    // The matched source and target constraints, and the positions on the
+    // trail and on the history of domains before their bindings at each level.
+    stage: Vec<(usize, usize, usize, usize)>,
+    // The target locals that each source local may still be bound to, if the
+    // domains are checked. A local without a domain is unrestricted.
+    forward: bool,
+    domain: HashMap<Variable, Vec<Variable>>,
+    history: Vec<(Variable, Option<Vec<Variable>>)>,
+    matched: Vec<bool>,
+    used: Vec<bool>,
+    // Target constraints by argument position and argument, for the
+    // positions where no target argument is an expression.
+    index: HashMap<(usize, Variable), Vec<usize>>,
+    exact: Vec<bool>,
+    // The source constraints using each local variable at those positions.
+    occurrence: HashMap<Variable, Vec<usize>>,
+    // The unmatched source constraints that can be chosen, by priority.
+    queue: BTreeSet<Priority>,
+    priority: Vec<Priority>,
+    // The previous identical constraint of each constraint. Identical
+    // constraints are matched in order, so that each permutation is found
+    // once.
+    source_twin: Vec<Option<usize>>,
+    target_twin: Vec<Option<usize>>,
+    next_twin: Vec<Option<usize>>,
+    // The source and target variables used by the constraints.
+    scope: (Vec<Variable>, Vec<Variable>),
     source: Vec<Constraint>,
+    target: Vec<Constraint>,
+    forbidden: Arc<Forbidden>,
 }
 
 impl GroupEnumerator {
-    fn new(source_group: Vec<Constraint>, target_group: Vec<Constraint>) -> Self {
+    fn new(
+        source_group: Vec<Constraint>,
+        target_group: Vec<Constraint>,
+        forward: bool,
+        forbidden: &Arc<Forbidden>,
+    ) -> Self {
+        let arity = source_group.first().map_or(0, |c| c.argument().len());
+        let exact = (0..arity)
+            .map(|p| target_group.iter().all(|c| !matches!(c.argument()[p], Variable::Expr(_))))
+            .collect_vec();
+        let index = target_group
+            .iter()
+            .enumerate()
+            .flat_map(|(t, c)| c.argument().iter().enumerate().map(move |(p, &v)| ((p, v), t)))
+            .filter(|((p, _), _)| exact[*p])
+            .into_group_map();
+        let occurrence = source_group
+            .iter()
+            .enumerate()
+            .flat_map(|(s, c)| c.argument().iter().enumerate().map(move |(p, &v)| (p, v, s)))
+            .filter(|&(p, v, _)| exact[p] && matches!(v, Variable::Local(_)))
+            .map(|(_, v, s)| (v, s))
+            .unique()
+            .into_group_map();
+        let (source_twin, next_twin) = twins(&source_group);
         Self {
-            choices: vec![target_group.clone()],
+            choices: Vec::new(),
             stage: Vec::new(),
+            forward,
+            domain: HashMap::new(),
+            history: Vec::new(),
+            matched: vec![false; source_group.len()],
+            used: vec![false; target_group.len()],
+            index,
+            exact,
+            occurrence,
+            queue: BTreeSet::new(),
+            priority: (0..source_group.len()).map(|s| (0, Reverse(0), s)).collect(),
+            source_twin,
+            target_twin: twins(&target_group).0,
+            next_twin,
+            // The outcome also depends on the variables sharing forbidden
+            // constraints with those of the group.
+            scope: forbidden.neighbours((&scope(&source_group), &scope(&target_group))),
             source: source_group,
-            target: target_group.into_iter().collect(),
+            target: target_group,
+            forbidden: forbidden.clone(),
         }
     }
 
+    // Check if the group enumerator has not started.
+    fn fresh(&self) -> bool {
+        self.choices.is_empty() && self.stage.is_empty()
+    }
+
+    // Check if every source constraint is matched.
+    fn complete(&self) -> bool {
+        self.stage.len() == self.source.len()
+    }
+
+    // Match the source constraints by the given choices of the remaining
+    // candidates, where every candidate matches, as if they were found by
+    // `advance`.
+    fn seek(&mut self, environment: &mut Environment, choices: impl Iterator<Item = usize>) {
+        self.refresh(environment);
+        for choice in choices {
+            self.open(environment);
+            let (s, candidates) = self.choices.last_mut().unwrap();
+            candidates.truncate(candidates.len() - choice);
+            let (s, t) = (*s, candidates.pop().unwrap());
+            self.commit(environment, s, t);
+        }
+        self.open(environment);
+    }
+
+    // Returns the position on the trail before the bindings of the group.
+    fn first_mark(&self) -> Option<usize> {
+        self.stage.first().map(|&(_, _, mark, _)| mark)
+    }
+
     // Reset the group enumerator and remove the bindings it created in the
     // environment.
-    fn reset(&mut self, environment: &mut BiMap<Variable, Variable>) {
-        self.stage.drain(..).for_each(|(focus, commit)| {
-            self.target.insert(focus);
-            commit.left_values().for_each(|t| {
-                environment.remove_by_left(t);
+    fn reset(&mut self, environment: &mut Environment) {
+        if let Some(&(_, _, mark, _)) = self.stage.first() {
+            environment.undo(mark);
+        }
+        self.clear();
+    }
+
+    // Reset the group enumerator, leaving the environment as it is.
+    fn clear(&mut self) {
+        self.stage.clear();
+        self.domain.clear();
+        self.history.clear();
+        self.matched.fill(false);
+        self.used.fill(false);
+        self.choices.clear();
+    }
+
+    // Encode the group enumerator into a checkpoint.
+    fn save(&self, writer: &mut Writer) {
+        writer.constraints(self.source.iter());
+        writer.constraints(self.target.iter());
+        writer.word(self.stage.len());
+        self.stage.iter().for_each(|&(s, t, _, _)| {
+            writer.word(s);
+            writer.word(t);
+        });
+        writer.word(self.choices.len());
+        self.choices.iter().for_each(|(s, candidates)| {
+            writer.word(*s);
+            writer.word(candidates.len());
+            candidates.iter().for_each(|&t| writer.word(t));
+        });
+    }
+
+    // Decode a group enumerator from a checkpoint and replay its stages in the
+    // environment, or `None` if its stages are inconsistent.
+    fn load(
+        reader: &mut Reader,
+        environment: &mut Environment,
+        forward: bool,
+        forbidden: &Arc<Forbidden>,
+    ) -> Result<Option<Self>, String> {
+        let mut loaded =
+            Self::new(reader.constraints()?, reader.constraints()?, forward, forbidden);
+        let stage = (0..reader.word()?)
+            .map(|_| Ok((reader.word()?, reader.word()?)))
+            .collect::<Result<Vec<_>, String>>()?;
+        let choices = (0..reader.word()?)
+            .map(|_| {
+                Ok((reader.word()?, (0..reader.word()?).map(|_| reader.word()).try_collect()?))
+            })
+            .collect::<Result<Vec<(usize, Vec<usize>)>, String>>()?;
+        if choices.is_empty() {
+            return Ok(stage.is_empty().then_some(loaded));
+        }
+        let consistent = loaded.refresh(environment)
+            && stage.into_iter().all(|(s, t)| {
+                s < loaded.source.len()
+                    && t < loaded.target.len()
+                    && loaded.eligible(s, true)
+                    && loaded.eligible(t, false)
+                    && loaded.commit(environment, s, t)
+            })
+            && choices.len() == loaded.stage.len() + 1
+            && choices.iter().all(|(s, candidates)| {
+                *s <= loaded.source.len() && candidates.iter().all(|&t| t < loaded.target.len())
             });
+        loaded.choices = choices;
+        Ok(consistent.then_some(loaded))
+    }
+
+    // Returns the positions and bound target variables of the arguments of a
+    // source constraint, which narrow down its target candidates.
+    fn bound<'e>(
+        &'e self,
+        environment: &'e Environment,
+        s: usize,
+    ) -> impl Iterator<Item = (usize, Variable)> + 'e {
+        self.source[s]
+            .argument()
+            .iter()
+            .enumerate()
+            .filter(|&(p, _)| self.exact[p])
+            .filter_map(|(p, v)| environment.target_of(v).map(|u| (p, u)))
+    }
+
+    fn bucket(&self, key: &(usize, Variable)) -> &[usize] {
+        self.index.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    // Update the priority of a source constraint, and its place in the queue
+    // if it can be chosen.
+    fn requeue(&mut self, environment: &Environment, s: usize) {
+        let queued = self.eligible(s, true) && self.queue.remove(&self.priority[s]);
+        let (candidates, bound) =
+            self.bound(environment, s).fold((self.target.len(), 0), |(candidates, bound), key| {
+                (candidates.min(self.bucket(&key).len()), bound + 1)
+            });
+        self.priority[s] = (candidates, Reverse(bound), s);
+        if queued {
+            self.queue.insert(self.priority[s]);
+        }
+    }
+
+    // Rebuild the queue under the environment, and check the domains of the
+    // source constraints bound by the previous groups. Returns `false` if some
+    // source constraint cannot be matched.
+    fn refresh(&mut self, environment: &Environment) -> bool {
+        self.queue.clear();
+        self.domain.clear();
+        self.history.clear();
+        (0..self.source.len()).for_each(|s| {
+            self.requeue(environment, s);
+            if self.eligible(s, true) {
+                self.queue.insert(self.priority[s]);
+            }
         });
-        self.choices.clear();
-        self.choices.push(self.target.clone().into_iter().collect());
+        let bound = (0..self.source.len()).filter(|&s| self.priority[s].1 .0 > 0).collect();
+        !self.forward || self.propagate(environment, bound)
+    }
+
+    // Requeue the source constraints using the variables.
+    fn touch(&mut self, environment: &Environment, variables: Vec<Variable>) {
+        variables.iter().for_each(|v| {
+            let users = self.occurrence.get(v).cloned().unwrap_or_default();
+            users.into_iter().for_each(|s| self.requeue(environment, s));
+        });
+    }
+
+    // Match a source constraint with a target constraint and commit the
+    // bindings to the environment, or return `false` if there is a conflict.
+    fn commit(&mut self, environment: &mut Environment, s: usize, t: usize) -> bool {
+        let mark = environment.mark();
+        if !environment.unify(&self.source[s], &self.target[t]) {
+            return false;
+        }
+        let bound = environment.bound_since(mark).collect_vec();
+        if self.forbidden.violated(environment, &bound) {
+            environment.undo(mark);
+            return false;
+        }
+        self.queue.remove(&self.priority[s]);
+        self.matched[s] = true;
+        self.used[t] = true;
+        self.stage.push((s, t, mark, self.history.len()));
+        if let Some(next) = self.next_twin[s] {
+            self.requeue(environment, next);
+            self.queue.insert(self.priority[next]);
+        }
+        self.touch(environment, bound.clone());
+        if self.forward {
+            let affected = bound.iter().flat_map(|v| self.occurrence.get(v)).flatten();
+            if !self.propagate(environment, affected.copied().collect()) {
+                self.undo(environment);
+                return false;
+            }
+        }
+        true
+    }
+
+    // Returns the target constraints sharing the bound arguments of a source
+    // constraint.
+    fn candidates(&self, environment: &Environment, s: usize) -> Vec<usize> {
+        match self.bound(environment, s).min_by_key(|key| self.bucket(key).len()) {
+            Some(key) => self.bucket(&key).to_vec(),
+            None => (0..self.target.len()).collect(),
+        }
+    }
+
+    // Check if a source constraint can still be matched with a target
+    // constraint, where the bound arguments agree and the unbound arguments
+    // are within their domains.
+    fn supports(&self, environment: &Environment, s: usize, t: usize) -> bool {
+        zip(self.source[s].argument(), self.target[t].argument())
+            .filter(|bind| !matches!(bind, (Variable::Expr(_), _) | (_, Variable::Expr(_))))
+            .all(|(v, u)| {
+                environment.compatible(v, u)
+                    && (environment.target_of(v).is_some()
+                        || self.domain.get(v).is_none_or(|domain| domain.binary_search(u).is_ok()))
+            })
+    }
+
+    // Narrow down the domains of the unbound locals of the source constraints
+    // to the arguments of the target constraints that support them, until no
+    // domain changes. Returns `false` if some unmatched source constraint has
+    // no support left.
+    fn propagate(&mut self, environment: &Environment, mut pending: Vec<usize>) -> bool {
+        let mut queued = vec![false; self.source.len()];
+        pending.iter().for_each(|&s| queued[s] = true);
+        while let Some(s) = pending.pop() {
+            queued[s] = false;
+            if self.matched[s] {
+                continue;
+            }
+            let support = self
+                .candidates(environment, s)
+                .into_iter()
+                .filter(|&t| !self.used[t] && self.supports(environment, s, t))
+                .collect_vec();
+            if support.is_empty() {
+                return false;
+            }
+            for (p, &v) in self.source[s].argument().iter().enumerate() {
+                if !self.exact[p]
+                    || !matches!(v, Variable::Local(_))
+                    || environment.target_of(&v).is_some()
+                {
+                    continue;
+                }
+                let allowed: HashSet<_> =
+                    support.iter().map(|&t| self.target[t].argument()[p]).collect();
+                let narrowed = match self.domain.get(&v) {
+                    Some(domain) => {
+                        domain.iter().copied().filter(|u| allowed.contains(u)).collect_vec()
+                    }
+                    None => allowed.into_iter().sorted().collect(),
+                };
+                if self.domain.get(&v).is_some_and(|domain| domain.len() == narrowed.len()) {
+                    continue;
+                }
+                if narrowed.is_empty() {
+                    return false;
+                }
+                let previous = self.domain.insert(v, narrowed);
+                self.history.push((v, previous));
+                for &r in &self.occurrence[&v] {
+                    if !queued[r] {
+                        queued[r] = true;
+                        pending.push(r);
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    // Undo the last match and the bindings it committed.
+    fn undo(&mut self, environment: &mut Environment) {
+        if let Some((s, t, mark, history)) = self.stage.pop() {
+            let unbound = environment.bound_since(mark).collect_vec();
+            environment.undo(mark);
+            self.history.drain(history..).rev().for_each(|(v, previous)| match previous {
+                Some(domain) => {
+                    self.domain.insert(v, domain);
+                }
+                None => {
+                    self.domain.remove(&v);
+                }
+            });
+            if let Some(next) = self.next_twin[s] {
+                self.queue.remove(&self.priority[next]);
+            }
+            self.matched[s] = false;
+            self.used[t] = false;
+            self.requeue(environment, s);
+            self.queue.insert(self.priority[s]);
+            self.touch(environment, unbound);
+        }
+    }
+
+    // Open a new level for the source constraint of the highest priority, with
+    // the target candidates sharing its bound arguments. The level is empty if
+    // every source constraint is matched.
+    fn open(&mut self, environment: &Environment) {
+        let level = self.queue.first().map_or((self.source.len(), Vec::new()), |&(_, _, s)| {
+            let candidates = self.candidates(environment, s).into_iter();
+            let candidates = candidates.filter(|&t| {
+                self.eligible(t, false) && (!self.forward || self.supports(environment, s, t))
+            });
+            (s, candidates.rev().collect())
+        });
+        self.choices.push(level);
+    }
+
+    // Check if a source or target constraint is the first unmatched one among
+    // the identical constraints.
+    fn eligible(&self, index: usize, source: bool) -> bool {
+        let (done, twin) = if source {
+            (&self.matched, &self.source_twin)
+        } else {
+            (&self.used, &self.target_twin)
+        };
+        !done[index] && twin[index].is_none_or(|previous| done[previous])
     }
 
     // Find the succeeding bindings for the group and commit them to the
     // environment.
-    fn advance(&mut self, environment: &mut BiMap<Variable, Variable>) -> bool {
-        use Variable::*;
-        while let Some(candidates) = self.choices.last_mut() {
-            if let Some(focus) = candidates.pop() {
-                let correspondence = self.source.get(self.choices.len() - 1).unwrap().clone();
-                if let Some(binding) = zip(focus.argument(), correspondence.argument())
-                    // Ignore bindings with expression variables.
-                    .filter(|&bind| !matches!(bind, (&Expr(_), _) | (_, &Expr(_))))
-                    .filter_map(|(u, v)| {
-                        // Assume that global variables are self-bind in the environment
-                        // Some(Some((u, v))) if u and v are not bind to any variable in the
-                        // environment Some(None) if u or v are already bind
-                        // to other variables in the environment
-                        // None if u and v are already bin to each other in the environment
-                        match (environment.get_by_left(u), environment.get_by_right(v)) {
-                            (None, None) => Some(Some((*u, *v))),
-                            (q, p) => (u != p.unwrap_or(u) || v != q.unwrap_or(v)).then(|| None),
-                        }
-                    })
-                    .try_fold(BiMap::new(), |mut introduced, bind| {
-                        // Bind u with v, and abort if there is conflict.
-                        bind.filter(|&(u, v)| introduced.insert_no_overwrite(u, v).is_ok())
-                            .map(|_| introduced)
-                    })
-                {
-                    // Commit bindings to the environment and advance in stage.
-                    self.target.remove(&focus);
-                    self.choices.push(self.target.clone().into_iter().collect());
-                    self.stage.push((focus, binding.clone()));
-                    environment.extend(binding);
-                    if self.target.is_empty() {
-                        return true;
+    fn advance(
+        &mut self,
+        environment: &mut Environment,
+        context: &mut Context,
+    ) -> Result<bool, Interrupt> {
+        if self.choices.is_empty() {
+            if !self.refresh(environment) {
+                return Ok(false);
+            }
+            self.open(environment);
+        }
+        while let Some((s, candidates)) = self.choices.last_mut() {
+            context.tick()?;
+            if let Some(t) = candidates.pop() {
+                context.record(|statistics| statistics.nodes += 1);
+                let s = *s;
+                if self.commit(environment, s, t) {
+                    context.push(self.stage.len());
+                    self.open(environment);
+                    if self.stage.len() == self.source.len() {
+                        return Ok(true);
                     }
+                } else {
+                    context.record(|statistics| statistics.rejected += 1);
                 }
             } else {
                 // Undo the last stage.
                 self.choices.pop();
-                if let Some((focus, commit)) = self.stage.pop() {
-                    self.target.insert(focus);
-                    commit.left_values().for_each(|t| {
-                        environment.remove_by_left(t);
-                    });
+                if !self.stage.is_empty() {
+                    context.pop(self.stage.len());
+                    self.undo(environment);
                 }
             }
         }
-        false
+        Ok(false)
     }
 }
@@ -0,0 +1,163 @@
+use std::iter::zip;
+use std::ops::Range;
+
+use crate::refine::Colouring;
+use crate::statement::{Constraint, Variable};
+
+// The bindings between source and target variables, indexed by their numbers.
+// Only variables of the same nonzero class can be bound to each other. The
+// bindings made during the search are recorded on a trail, so that they can be
+// undone in the reverse order.
+#[derive(Clone, Debug)]
+pub(crate) struct Environment {
+    source: Vec<Variable>,
+    target: Vec<Variable>,
+    forward: Vec<Option<u32>>,
+    backward: Vec<Option<u32>>,
+    source_class: Vec<u32>,
+    target_class: Vec<u32>,
+    trail: Vec<u32>,
+    // The position on the trail of the binding of each source variable.
+    since: Vec<u32>,
+}
+
+impl Environment {
+    // Create an environment where each pair of global variables is bound, and
+    // local variables can be bound to each other.
+    pub(crate) fn new(
+        source: Vec<Variable>,
+        target: Vec<Variable>,
+        global: &[(Variable, Variable)],
+    ) -> Self {
+        let class = |v: &Variable| matches!(v, Variable::Local(_)) as u32;
+        let mut environment = Self {
+            forward: vec![None; source.len()],
+            backward: vec![None; target.len()],
+            source_class: source.iter().map(class).collect(),
+            since: vec![u32::MAX; source.len()],
+            target_class: target.iter().map(class).collect(),
+            source,
+            target,
+            trail: Vec::new(),
+        };
+        global.iter().for_each(|&(v, u)| {
+            environment.forward[v.index()] = Some(u.index() as u32);
+            environment.backward[u.index()] = Some(v.index() as u32);
+        });
+        environment
+    }
+
+    // Restrict the bindings of locals to those of the same colour.
+    pub(crate) fn colour(&mut self, (source, target): Colouring) {
+        let restrict = |variables: &[Variable], class: &mut [u32], colour: Vec<u32>| {
+            zip(variables, zip(class, colour))
+                .filter(|(v, _)| matches!(v, Variable::Local(_)))
+                .for_each(|(_, (class, colour))| *class = colour + 1);
+        };
+        restrict(&self.source, &mut self.source_class, source);
+        restrict(&self.target, &mut self.target_class, target);
+    }
+
+    // Returns the number of source variables.
+    pub(crate) fn source_count(&self) -> usize {
+        self.source.len()
+    }
+
+    pub(crate) fn target_of(&self, v: &Variable) -> Option<Variable> {
+        self.forward[v.index()].map(|u| self.target[u as usize])
+    }
+
+    pub(crate) fn source_of(&self, u: &Variable) -> Option<Variable> {
+        self.backward[u.index()].map(|v| self.source[v as usize])
+    }
+
+    // Returns all bound pairs of source and target variables.
+    pub(crate) fn pairs(&self) -> impl Iterator<Item = (Variable, Variable)> + '_ {
+        zip(&self.source, &self.forward)
+            .filter_map(|(&v, u)| u.map(|u| (v, self.target[u as usize])))
+    }
+
+    // Returns the position on the trail, to undo the bindings made after it.
+    pub(crate) fn mark(&self) -> usize {
+        self.trail.len()
+    }
+
+    // Returns the position on the trail of the binding of a source variable,
+    // or `None` if it is unbound or bound from the start.
+    pub(crate) fn position(&self, v: &Variable) -> Option<usize> {
+        let since = self.since[v.index()] as usize;
+        (self.forward[v.index()].is_some() && since < self.trail.len()).then_some(since)
+    }
+
+    // Returns the pairs bound within the range of positions on the trail.
+    pub(crate) fn bound_in(
+        &self,
+        range: Range<usize>,
+    ) -> impl Iterator<Item = (Variable, Variable)> + '_ {
+        self.trail[range].iter().map(|&v| {
+            (self.source[v as usize], self.target[self.forward[v as usize].unwrap() as usize])
+        })
+    }
+
+    // Returns the source variables bound after the position on the trail.
+    pub(crate) fn bound_since(&self, mark: usize) -> impl Iterator<Item = Variable> + '_ {
+        self.trail[mark..].iter().map(|&v| self.source[v as usize])
+    }
+
+    pub(crate) fn undo(&mut self, mark: usize) {
+        self.trail.drain(mark..).for_each(|v| {
+            if let Some(u) = self.forward[v as usize].take() {
+                self.backward[u as usize] = None;
+            }
+        });
+    }
+
+    // Check if a source variable is bound to a target variable, or both can
+    // be bound to each other.
+    pub(crate) fn compatible(&self, v: &Variable, u: &Variable) -> bool {
+        let (v, u) = (v.index(), u.index());
+        match (self.forward[v], self.backward[u]) {
+            (None, None) => {
+                self.source_class[v] != 0 && self.source_class[v] == self.target_class[u]
+            }
+            (q, p) => q == Some(u as u32) && p == Some(v as u32),
+        }
+    }
+
+    // Bind a pair of variables from the start, as the pairs of globals are.
+    // Returns `false` and leaves the environment unchanged if they cannot be
+    // bound to each other.
+    pub(crate) fn pin(&mut self, v: &Variable, u: &Variable) -> bool {
+        let (i, j) = (v.index(), u.index());
+        let pinned = self.forward[i].is_none() && self.compatible(v, u);
+        if pinned {
+            self.forward[i] = Some(j as u32);
+            self.backward[j] = Some(i as u32);
+        }
+        pinned
+    }
+
+    // Bind the arguments of a source constraint to those of a target
+    // constraint, ignoring expression variables. Returns `false` and leaves the
+    // environment unchanged if there is a conflict.
+    pub(crate) fn unify(&mut self, source: &Constraint, target: &Constraint) -> bool {
+        let mark = self.mark();
+        let unified = zip(source.argument(), target.argument())
+            .filter(|bind| !matches!(bind, (Variable::Expr(_), _) | (_, Variable::Expr(_))))
+            .all(|(v, u)| {
+                let compatible = self.compatible(v, u);
+                let (v, u) = (v.index(), u.index());
+                if compatible && self.forward[v].is_none() {
+                    self.forward[v] = Some(u as u32);
+                    self.backward[u] = Some(v as u32);
+                    self.since[v] = self.trail.len() as u32;
+                    self.trail.push(v as u32);
+                }
+                compatible
+            });
+        if !unified {
+            self.undo(mark);
+        }
+        unified
+    }
+}
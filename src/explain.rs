@@ -0,0 +1,71 @@
+use crate::align::{Instance, Statement};
+use crate::search::{Context, Interrupt, Limit};
+use crate::wrapper::Builder;
+use std::hash::Hash;
+
+/// # The core struct.
+/// A core is a subset of the source constraints that cannot be matched into
+/// distinct target constraints under any mapping of local variables, such that
+/// every smaller subset of it can. It is the smallest structural reason why the
+/// source bag does not fit the target bag.
+pub struct Core<R, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The constraints of the core, in the order they are given.
+    pub constraints: Vec<Statement<R, U, V, W>>,
+    /// The reason why the search was cut short, or `None` if the core is known
+    /// to be minimal. If the search is cut short, the core is still known not
+    /// to be matched, unless it is the whole source bag.
+    pub interrupted: Option<Interrupt>,
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Clone + Eq + Hash,
+    T: Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    /// Explain why the inputs have no permutation, by shrinking the source
+    /// constraints to a minimal core that cannot be matched into the target
+    /// constraints. Returns `None` if the source constraints can be matched as
+    /// a whole, in which case the bags differ only by the target constraints
+    /// left over. The search is bounded by the limit as a whole, and returns an
+    /// error if the inputs are rejected by `align`.
+    pub fn explain(self, limit: Limit) -> Result<Option<Core<R, U, V, W>>, String> {
+        let instance = Instance::new(self)?;
+        let mut context = Context::new(&limit, None, None);
+        // A subset is matched if its largest alignment covers all of it.
+        let mut matched = |subset: &[usize]| {
+            let (pairs, interrupted) = instance.largest(subset.iter().copied(), &mut context);
+            match interrupted {
+                _ if pairs.len() == subset.len() => Ok(true),
+                Some(interrupt) => Err(interrupt),
+                None => Ok(false),
+            }
+        };
+        let mut core = (0..instance.source.len()).collect::<Vec<_>>();
+        let mut interrupted = None;
+        match matched(&core) {
+            Ok(true) => return Ok(None),
+            Ok(false) => {}
+            Err(interrupt) => interrupted = Some(interrupt),
+        }
+        // Drop each constraint in turn if the rest is still not matched.
+        let mut index = 0;
+        while interrupted.is_none() && index < core.len() {
+            let rest = [&core[..index], &core[index + 1..]].concat();
+            match matched(&rest) {
+                Ok(true) => index += 1,
+                Ok(false) => core = rest,
+                Err(interrupt) => interrupted = Some(interrupt),
+            }
+        }
+        let constraints = core.into_iter().map(|s| instance.source_statements[s].clone()).collect();
+        Ok(Some(Core { constraints, interrupted }))
+    }
+}
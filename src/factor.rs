@@ -0,0 +1,564 @@
+use crate::statement::fingerprint;
+use crate::wrapper::{Builder, Isoperm, Permutation, Var};
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::iter::zip;
+
+type Bag<R, U, V, W> = Vec<(R, Vec<Var<U, V, W>>)>;
+// A mapping between the locals of two components by their positions.
+type Mapping = Vec<usize>;
+type Variables<T, U, V, W> = HashMap<Var<U, V, W>, T>;
+// The constraints without locals and the connected components of a bag.
+type Split<R, U, V, W> = (Bag<R, U, V, W>, Vec<Component<R, U, V, W>>);
+// A component with the variables of its bag.
+type Side<'c, R, T, U, V, W> = (&'c Component<R, U, V, W>, &'c Variables<T, U, V, W>);
+// A bag with its variables.
+type Input<'b, R, T, U, V, W> = (&'b Bag<R, U, V, W>, &'b Variables<T, U, V, W>);
+// The identifiers of the constraints with expressions, and the types of the
+// locals that may face expressions.
+type Blurred<'b, R, T> = (HashSet<&'b R>, HashSet<&'b T>);
+// A factor with its representative component.
+type Class<R, U, V, W> = (Component<R, U, V, W>, Factor<U, V, W>);
+
+// A connected component of a bag, with its locals in the order of first use.
+// The component of the constraints with expressions is marked.
+struct Component<R, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    constraints: Bag<R, U, V, W>,
+    local: Vec<Var<U, V, W>>,
+    invariant: (Vec<u64>, Vec<u64>),
+    expression: bool,
+}
+
+/// # The factorization struct.
+/// The permutations of two bags whose constraints split into parts sharing no
+/// local variables are the products of the permutations of the parts. A
+/// factorization groups the connected components of both bags into factors of
+/// isomorphic components, where each local variable used by no constraint is
+/// a component by itself. Expressions match any argument, so the constraints
+/// with the identifiers of those with expressions are joined into a single
+/// component on each side, together with the locals of the types that may
+/// face expressions. The permutations are expanded lazily by `iter()`, and
+/// global variables are mapped to themselves.
+pub struct Factorization<U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    global: Vec<Var<U, V, W>>,
+    factors: Vec<Factor<U, V, W>>,
+    feasible: bool,
+}
+
+/// # The factor struct.
+/// A factor consists of isomorphic source and target components. Each source
+/// component is mapped to a distinct target component by one of their
+/// isomorphisms, which are all derived from the automorphisms of the first
+/// source component of the factor. The components with expressions form a
+/// factor of their own, whose isomorphisms are found directly and stand for
+/// the automorphisms.
+pub struct Factor<U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source: Vec<Vec<Var<U, V, W>>>,
+    target: Vec<Vec<Var<U, V, W>>>,
+    // An isomorphism from each source component to the representative.
+    to_representative: Vec<Mapping>,
+    // An isomorphism from the representative to each target component.
+    from_representative: Vec<Mapping>,
+    automorphisms: Vec<Mapping>,
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Clone + Eq + Hash,
+    T: Clone + Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    /// Factorize the permutations of the inputs by the connected components of
+    /// the bags, where the optional constraints are left out. Returns an error
+    /// if the inputs are rejected by `build()`, or if they have forbidden
+    /// constraints, aliases or renamed globals, which are not factorized.
+    pub fn factorize(self) -> Result<Factorization<U, V, W>, String> {
+        let forbidden = !self.source_forbidden.is_empty() || !self.target_forbidden.is_empty();
+        if forbidden || !self.aliases.is_empty() || self.config.renaming {
+            return Err(String::from("Unsupported inputs for factorization."));
+        }
+        let (source_constraints, source_variables) = self.source.clone();
+        let (target_constraints, target_variables) = self.target.clone();
+        // Validate the inputs as a whole.
+        self.build()?;
+        let global = source_variables
+            .keys()
+            .chain(target_variables.keys())
+            .filter(|v| matches!(v, Var::Global(_)))
+            .unique()
+            .cloned()
+            .collect();
+        let blurred = blurred(
+            (&source_constraints, &source_variables),
+            (&target_constraints, &target_variables),
+        );
+        let (source_ground, source_components) =
+            components(source_constraints.clone(), &source_variables, &blurred);
+        let (target_ground, target_components) =
+            components(target_constraints.clone(), &target_variables, &blurred);
+        // Constraints without locals must match as they are.
+        let ground = |constraints| Component {
+            constraints,
+            local: vec![],
+            invariant: <_>::default(),
+            expression: false,
+        };
+        let mut feasible = !mappings(
+            (&ground(source_ground), &source_variables),
+            (&ground(target_ground), &target_variables),
+            true,
+        )
+        .is_empty();
+        let mut factors: Vec<Class<R, U, V, W>> = Vec::new();
+        for component in source_components {
+            let found = factors.iter_mut().find_map(|(representative, factor)| {
+                (representative.invariant == component.invariant && !component.expression)
+                    .then(|| {
+                        mappings(
+                            (&component, &source_variables),
+                            (representative, &source_variables),
+                            true,
+                        )
+                    })
+                    .and_then(|mut mapping| mapping.pop())
+                    .map(|mapping| (factor, mapping))
+            });
+            match found {
+                Some((factor, mapping)) => {
+                    factor.source.push(component.local);
+                    factor.to_representative.push(mapping);
+                }
+                None => {
+                    // The isomorphisms of the component with expressions are
+                    // found with its target component.
+                    let automorphisms = if component.expression {
+                        vec![]
+                    } else {
+                        mappings(
+                            (&component, &source_variables),
+                            (&component, &source_variables),
+                            false,
+                        )
+                    };
+                    let factor = Factor {
+                        source: vec![component.local.clone()],
+                        target: vec![],
+                        to_representative: vec![(0..component.local.len()).collect()],
+                        from_representative: vec![],
+                        automorphisms,
+                    };
+                    factors.push((component, factor));
+                }
+            }
+        }
+        for component in target_components {
+            let found = factors.iter_mut().find_map(|(representative, factor)| {
+                if representative.invariant != component.invariant
+                    || representative.expression != component.expression
+                {
+                    return None;
+                }
+                let source = (&*representative, &source_variables);
+                let target = (&component, &target_variables);
+                if !component.expression {
+                    return mappings(source, target, true).pop().map(|mapping| (factor, mapping));
+                }
+                // The isomorphisms stand for the automorphisms, mapped to the
+                // target component as they are.
+                let isomorphisms = mappings(source, target, false);
+                (!isomorphisms.is_empty()).then(|| {
+                    factor.automorphisms = isomorphisms;
+                    (factor, (0..component.local.len()).collect())
+                })
+            });
+            match found {
+                Some((factor, mapping)) => {
+                    factor.target.push(component.local);
+                    factor.from_representative.push(mapping);
+                }
+                None => feasible = false,
+            }
+        }
+        let factors = factors.into_iter().map(|(_, factor)| factor).collect_vec();
+        let feasible = feasible && factors.iter().all(|f| f.source.len() == f.target.len());
+        Ok(Factorization { global, factors, feasible })
+    }
+}
+
+// Find the identifiers of the constraints with expressions on either side, and
+// the types of the locals at the positions where some constraint of the same
+// identifier has an expression.
+fn blurred<'b, R, T, U, V, W>(
+    (source, source_variables): Input<'b, R, T, U, V, W>,
+    (target, target_variables): Input<'b, R, T, U, V, W>,
+) -> Blurred<'b, R, T>
+where
+    R: Eq + Hash,
+    T: Eq + Hash,
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    let arguments = || {
+        let source = source.iter().map(move |c| (c, source_variables));
+        let target = target.iter().map(move |c| (c, target_variables));
+        source.chain(target).flat_map(|((r, argument), variables)| {
+            argument.iter().enumerate().map(move |(p, v)| (r, p, v, variables))
+        })
+    };
+    let position: HashSet<_> = arguments()
+        .filter(|(_, _, v, _)| matches!(v, Var::Expr(_)))
+        .map(|(r, p, _, _)| (r, p))
+        .collect();
+    let loose = arguments()
+        .filter(|(r, p, v, _)| matches!(v, Var::Local(_)) && position.contains(&(*r, *p)))
+        .map(|(_, _, v, variables)| &variables[v])
+        .collect();
+    (position.into_iter().map(|(r, _)| r).collect(), loose)
+}
+
+// Split a bag into the constraints without locals and the connected
+// components, followed by a component for each unused local. The constraints
+// of the blurred identifiers and the locals of the blurred types are joined
+// into a single component.
+fn components<R, T, U, V, W>(
+    constraints: Bag<R, U, V, W>,
+    variables: &HashMap<Var<U, V, W>, T>,
+    (identifiers, loose): &Blurred<R, T>,
+) -> Split<R, U, V, W>
+where
+    R: Eq + Hash,
+    T: Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    // Number the locals in the order of first use, and unite the locals used
+    // by the same constraint.
+    let mut number = HashMap::new();
+    let mut parent: Vec<usize> = Vec::new();
+    fn root(parent: &mut [usize], mut i: usize) -> usize {
+        while parent[i] != i {
+            parent[i] = parent[parent[i]];
+            i = parent[i];
+        }
+        i
+    }
+    fn unite(parent: &mut [usize], local: &[usize]) {
+        local.iter().tuple_windows().for_each(|(&i, &j)| {
+            let (i, j) = (root(parent, i), root(parent, j));
+            parent[i.max(j)] = i.min(j);
+        });
+    }
+    for (_, argument) in &constraints {
+        let local = argument
+            .iter()
+            .filter(|v| matches!(v, Var::Local(_)))
+            .map(|v| {
+                let next = number.len();
+                let i = *number.entry(v.clone()).or_insert(next);
+                if i == parent.len() {
+                    parent.push(i);
+                }
+                i
+            })
+            .collect_vec();
+        unite(&mut parent, &local);
+    }
+    let blurred_type = |v: &Var<U, V, W>| loose.contains(&variables[v]);
+    let blurred = constraints.iter().filter(|(r, _)| identifiers.contains(r));
+    let joined = blurred
+        .flat_map(|(_, argument)| argument)
+        .chain(number.keys().filter(|v| blurred_type(v)))
+        .filter_map(|v| number.get(v).copied())
+        .collect_vec();
+    unite(&mut parent, &joined);
+    // The joined component holds the blurred constraints without locals too.
+    let joined = joined.first().map_or(usize::MAX, |&i| root(&mut parent, i));
+    let (ground, constraints): (Vec<_>, Vec<_>) =
+        constraints.into_iter().partition(|(r, argument)| {
+            !identifiers.contains(r) && argument.iter().all(|v| !matches!(v, Var::Local(_)))
+        });
+    let mut component: HashMap<usize, Component<R, U, V, W>> = HashMap::new();
+    let mut order = Vec::new();
+    let fresh = |key: usize| Component {
+        constraints: vec![],
+        local: vec![],
+        invariant: <_>::default(),
+        expression: key == joined,
+    };
+    for constraint in constraints {
+        let first = constraint.1.iter().find(|v| matches!(v, Var::Local(_)));
+        let key = first.map_or(joined, |first| root(&mut parent, number[first]));
+        component
+            .entry(key)
+            .or_insert_with(|| {
+                order.push(key);
+                fresh(key)
+            })
+            .constraints
+            .push(constraint);
+    }
+    let (joined_unused, unused): (Vec<_>, Vec<_>) = variables
+        .keys()
+        .filter(|v| matches!(v, Var::Local(_)) && !number.contains_key(v))
+        .sorted_by_cached_key(fingerprint)
+        .partition(|v| blurred_type(v));
+    number.into_iter().sorted_by_key(|(_, i)| *i).for_each(|(v, i)| {
+        let key = root(&mut parent, i);
+        component.get_mut(&key).unwrap().local.push(v);
+    });
+    joined_unused.into_iter().for_each(|v| {
+        let joined = component.entry(joined).or_insert_with(|| {
+            order.push(joined);
+            fresh(joined)
+        });
+        joined.local.push(v.clone());
+    });
+    let unused = unused
+        .into_iter()
+        .map(|v| Component {
+            constraints: vec![],
+            local: vec![v.clone()],
+            invariant: <_>::default(),
+            expression: false,
+        })
+        .collect_vec();
+    let components = order
+        .into_iter()
+        .map(|key| component.remove(&key).unwrap())
+        .chain(unused)
+        .map(|mut c| {
+            c.invariant = (
+                c.constraints.iter().map(|(r, _)| fingerprint(r)).sorted().collect(),
+                c.local.iter().map(|v| fingerprint(&variables[v])).sorted().collect(),
+            );
+            c
+        })
+        .collect();
+    (ground, components)
+}
+
+// Find the isomorphisms from one component to another, or only the first one.
+fn mappings<R, T, U, V, W>(
+    (source, source_variables): Side<R, T, U, V, W>,
+    (target, target_variables): Side<R, T, U, V, W>,
+    first: bool,
+) -> Vec<Mapping>
+where
+    R: Clone + Eq + Hash,
+    T: Clone + Eq + Hash,
+    U: Clone + Eq + Hash + PartialEq,
+    V: Clone + Eq + Hash + PartialEq,
+    W: Clone + Eq + Hash + PartialEq,
+{
+    // Restrict the variables to those used by the component.
+    let restrict = |component: &Component<R, U, V, W>, variables: &Variables<T, U, V, W>| {
+        component
+            .constraints
+            .iter()
+            .flat_map(|(_, argument)| argument)
+            .chain(&component.local)
+            .map(|v| (v.clone(), variables[v].clone()))
+            .collect()
+    };
+    let isoperm = Isoperm::new(
+        source.constraints.clone(),
+        restrict(source, source_variables),
+        target.constraints.clone(),
+        restrict(target, target_variables),
+    );
+    let position: HashMap<_, _> = target.local.iter().enumerate().map(|(i, v)| (v, i)).collect();
+    // Every local is bound, including those facing expressions, which are
+    // bound as the free locals are. A mapping may be found once for each
+    // matching of the constraints with expressions.
+    isoperm.map_or(vec![], |mut isoperm| {
+        isoperm
+            .result()
+            .map(|binding| {
+                source.local.iter().map(|v| position[binding.get_by_left(v).unwrap()]).collect()
+            })
+            .unique()
+            .take(if first { 1 } else { usize::MAX })
+            .collect()
+    })
+}
+
+impl<U, V, W> Factorization<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns the factors, or `None` if the bags have no permutation.
+    pub fn factors(&self) -> Option<&[Factor<U, V, W>]> {
+        self.feasible.then_some(self.factors.as_slice())
+    }
+
+    /// Returns the number of permutations, or `None` if it overflows.
+    pub fn count(&self) -> Option<u128> {
+        self.factors().map_or(Some(0), |factors| {
+            factors.iter().try_fold(1u128, |n, f| n.checked_mul(f.count()?))
+        })
+    }
+
+    /// Returns the iterator of all permutations, which are expanded from the
+    /// factors in turn.
+    pub fn iter(&self) -> Product<'_, U, V, W> {
+        let state = self.factors().map(|factors| {
+            factors
+                .iter()
+                .map(|f| ((0..f.source.len()).collect(), vec![0; f.source.len()]))
+                .collect()
+        });
+        Product { factorization: self, state, started: false }
+    }
+}
+
+impl<U, V, W> Factor<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns the local variables of each source component.
+    pub fn source(&self) -> impl Iterator<Item = &[Var<U, V, W>]> {
+        self.source.iter().map(Vec::as_slice)
+    }
+
+    /// Returns the local variables of each target component.
+    pub fn target(&self) -> impl Iterator<Item = &[Var<U, V, W>]> {
+        self.target.iter().map(Vec::as_slice)
+    }
+
+    /// Returns the number of automorphisms of each component.
+    pub fn automorphisms(&self) -> usize {
+        self.automorphisms.len()
+    }
+
+    /// Returns the number of ways to map the source components to the target
+    /// components, or `None` if it overflows.
+    pub fn count(&self) -> Option<u128> {
+        let k = self.source.len() as u128;
+        let arrangements = (1..=k).try_fold(1u128, |n, i| n.checked_mul(i))?;
+        (self.automorphisms.len() as u128)
+            .checked_pow(k as u32)
+            .and_then(|automorphisms| arrangements.checked_mul(automorphisms))
+    }
+
+    /// Returns the isomorphisms from the `i`-th source component to the
+    /// `j`-th target component.
+    pub fn mappings(&self, i: usize, j: usize) -> impl Iterator<Item = Permutation<'_, U, V, W>> {
+        (0..self.automorphisms.len()).map(move |a| self.mapping(i, j, a).collect())
+    }
+
+    // Map the `i`-th source component to the `j`-th target component through
+    // the `a`-th automorphism of the representative.
+    fn mapping(
+        &self,
+        i: usize,
+        j: usize,
+        a: usize,
+    ) -> impl Iterator<Item = (&Var<U, V, W>, &Var<U, V, W>)> {
+        self.source[i].iter().zip(&self.to_representative[i]).map(move |(v, &r)| {
+            (v, &self.target[j][self.from_representative[j][self.automorphisms[a][r]]])
+        })
+    }
+}
+
+/// The lazily expanded iterator of the permutations of a factorization.
+pub struct Product<'f, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    factorization: &'f Factorization<U, V, W>,
+    // The target component and the automorphism chosen for each source
+    // component of each factor.
+    state: Option<Vec<(Vec<usize>, Vec<usize>)>>,
+    started: bool,
+}
+
+impl<'f, U, V, W> Product<'f, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    // Move to the next choices like an odometer, where the automorphisms turn
+    // faster than the arrangements of the components.
+    fn advance(&mut self) -> bool {
+        let factors = &self.factorization.factors;
+        let state = self.state.as_mut().unwrap();
+        zip(factors, state).rev().any(|(factor, (order, choice))| {
+            let turned =
+                (0..choice.len()).rev().find(|&i| choice[i] + 1 < factor.automorphisms.len());
+            if let Some(i) = turned {
+                choice[i] += 1;
+                choice[i + 1..].fill(0);
+                return true;
+            }
+            choice.fill(0);
+            next_permutation(order) || {
+                order.sort();
+                false
+            }
+        })
+    }
+}
+
+impl<'f, U, V, W> Iterator for Product<'f, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    type Item = Permutation<'f, U, V, W>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.state.as_ref()?;
+        if std::mem::replace(&mut self.started, true) && !self.advance() {
+            self.state = None;
+            return None;
+        }
+        let factorization = self.factorization;
+        let state = self.state.as_ref().unwrap();
+        let local = zip(&factorization.factors, state).flat_map(|(factor, (order, choice))| {
+            (0..order.len()).flat_map(move |i| factor.mapping(i, order[i], choice[i]))
+        });
+        Some(local.chain(factorization.global.iter().map(|v| (v, v))).collect())
+    }
+}
+
+// Rearrange into the next permutation in lexicographic order, or return
+// `false` if it is the last one.
+fn next_permutation(order: &mut [usize]) -> bool {
+    match (1..order.len()).rev().find(|&i| order[i - 1] < order[i]) {
+        Some(i) => {
+            let j = (i..order.len()).rev().find(|&j| order[i - 1] < order[j]).unwrap();
+            order.swap(i - 1, j);
+            order[i..].reverse();
+            true
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::HashSet;
+use std::iter::zip;
+
+use itertools::Itertools;
+
+use crate::environment::Environment;
+use crate::statement::{Constraint, Variable};
+
+// The constraints forbidden on each side, which must not be present on the
+// other side under the bindings once all their arguments are bound.
+// Expression variables are matched to anything, as in the constraints.
+#[derive(Debug, Hash)]
+pub(crate) struct Forbidden {
+    source: Vec<Constraint>,
+    target: Vec<Constraint>,
+    // The forbidden constraints using each variable, and the constraints
+    // present by signature, on each side.
+    watch: (Index<Variable, usize>, Index<Variable, usize>),
+    present: (Index<usize, Constraint>, Index<usize, Constraint>),
+}
+
+// Values grouped by sorted keys, which are looked up by binary search.
+type Index<K, T> = Vec<(K, Vec<T>)>;
+
+impl Forbidden {
+    pub(crate) fn new(
+        (source, target): (Vec<Constraint>, Vec<Constraint>),
+        (source_present, target_present): (&[Constraint], &[Constraint]),
+    ) -> Self {
+        let watch = |forbidden: &[Constraint]| {
+            let users = forbidden.iter().enumerate().flat_map(|(f, c)| {
+                let argument = c.argument().iter().filter(|v| !matches!(v, Variable::Expr(_)));
+                argument.unique().map(move |&v| (v, f))
+            });
+            users.into_group_map().into_iter().sorted().collect()
+        };
+        let present = |constraints: &[Constraint]| {
+            let signature = constraints.iter().map(|c| (c.signature(), c.clone()));
+            let grouped = signature.into_group_map().into_iter();
+            grouped.map(|(k, v)| (k, v.into_iter().sorted().collect())).sorted().collect()
+        };
+        Self {
+            watch: (watch(&source), watch(&target)),
+            present: (present(source_present), present(target_present)),
+            source,
+            target,
+        }
+    }
+
+    // Returns the locals used by the forbidden constraints of each side.
+    pub(crate) fn locals(&self) -> (HashSet<Variable>, HashSet<Variable>) {
+        let locals = |watch: &Index<Variable, usize>| {
+            let variables = watch.iter().map(|(v, _)| *v);
+            variables.filter(|v| matches!(v, Variable::Local(_))).collect()
+        };
+        (locals(&self.watch.0), locals(&self.watch.1))
+    }
+
+    // Returns the variables sharing a forbidden constraint with the variables
+    // of each side, including themselves.
+    pub(crate) fn neighbours(
+        &self,
+        (source, target): (&[Variable], &[Variable]),
+    ) -> (Vec<Variable>, Vec<Variable>) {
+        let neighbours = |forbidden: &[Constraint], watch, scope: &[Variable]| {
+            let used = scope.iter().flat_map(|v| users(watch, v));
+            let argument = used.flat_map(|&f| forbidden[f].argument());
+            let argument = argument.filter(|v| !matches!(v, Variable::Expr(_)));
+            scope.iter().chain(argument).copied().unique().collect()
+        };
+        (
+            neighbours(&self.source, &self.watch.0, source),
+            neighbours(&self.target, &self.watch.1, target),
+        )
+    }
+
+    // Check if a forbidden constraint using the newly bound source variables
+    // or their targets is present on the other side.
+    pub(crate) fn violated(&self, environment: &Environment, bound: &[Variable]) -> bool {
+        let source = bound.iter().flat_map(|v| users(&self.watch.0, v));
+        let target = bound.iter().filter_map(|v| environment.target_of(v));
+        let target = target.flat_map(|u| users(&self.watch.1, &u)).collect_vec();
+        self.any(environment, source.copied(), target.into_iter().copied())
+    }
+
+    // Check if any forbidden constraint is present on the other side under
+    // the bindings.
+    pub(crate) fn violated_any(&self, environment: &Environment) -> bool {
+        self.any(environment, 0..self.source.len(), 0..self.target.len())
+    }
+
+    fn any(
+        &self,
+        environment: &Environment,
+        mut source: impl Iterator<Item = usize>,
+        mut target: impl Iterator<Item = usize>,
+    ) -> bool {
+        source.any(|f| present(&self.source[f], |v| environment.target_of(v), &self.present.1))
+            || target
+                .any(|f| present(&self.target[f], |u| environment.source_of(u), &self.present.0))
+    }
+}
+
+fn users<'f>(watch: &'f Index<Variable, usize>, v: &Variable) -> &'f [usize] {
+    watch.binary_search_by_key(v, |(w, _)| *w).map_or(&[], |position| watch[position].1.as_slice())
+}
+
+// Check if a forbidden constraint is present on the other side, once its
+// arguments are all bound.
+fn present(
+    forbidden: &Constraint,
+    bind: impl Fn(&Variable) -> Option<Variable>,
+    present: &Index<usize, Constraint>,
+) -> bool {
+    let argument = forbidden.argument().iter().map(|v| match v {
+        Variable::Expr(_) => Some(*v),
+        _ => bind(v),
+    });
+    let Some(argument) = argument.collect::<Option<Vec<_>>>() else {
+        return false;
+    };
+    let Ok(position) = present.binary_search_by_key(&forbidden.signature(), |(k, _)| *k) else {
+        return false;
+    };
+    present[position].1.iter().any(|c| {
+        c.argument().len() == argument.len()
+            && zip(c.argument(), &argument).all(|pair| match pair {
+                (Variable::Expr(_), _) | (_, Variable::Expr(_)) => true,
+                (v, u) => v == u,
+            })
+    })
+}
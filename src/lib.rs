@@ -10,7 +10,22 @@
 //! the two bags of constraints can be evaluated to the same bag of results
 //! under such mappings.
 
+pub mod align;
+pub mod checkpoint;
+pub mod config;
+pub mod diff;
 mod enumerator;
+mod environment;
+pub mod explain;
+pub mod factor;
+mod forbid;
+pub mod optimize;
+pub mod optional;
+pub mod parallel;
+pub mod project;
+mod refine;
+pub mod sample;
+pub mod search;
 mod statement;
 pub mod wrapper;
 
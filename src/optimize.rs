@@ -0,0 +1,53 @@
+use crate::search::{Context, Interrupt, Limit};
+use crate::wrapper::{translate, Isoperm, Permutation, Var};
+use std::hash::Hash;
+
+/// # The optimum struct.
+/// The permutations of the lowest costs found by `Isoperm::optimize`.
+pub struct Optimum<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The permutations with their costs, from the lowest cost. Permutations
+    /// of the same cost are in the order they are enumerated.
+    pub best: Vec<(Permutation<'t, U, V, W>, f64)>,
+    /// The reason why the search was cut short, or `None` if the permutations
+    /// are known to have the lowest costs.
+    pub interrupted: Option<Interrupt>,
+}
+
+impl<U, V, W> Isoperm<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Find the given number of permutations of the lowest costs, where the
+    /// cost of a permutation is the sum of the costs of its pairs of local
+    /// variables. The search prunes the subtrees whose costs cannot be among
+    /// the lowest found so far, and is bounded by the limit as a whole, in
+    /// which case the best permutations found are returned. The enumeration
+    /// of the instance is left as it is.
+    pub fn optimize(
+        &self,
+        top: usize,
+        limit: Limit,
+        mut cost: impl FnMut(&Var<U, V, W>, &Var<U, V, W>) -> f64,
+    ) -> Optimum<'_, U, V, W> {
+        let (source, target) = (&self.source_translation, &self.target_translation);
+        let mut context = Context::new(&limit, None, None);
+        let (best, interrupted) = self.permutation.optimize(
+            top.max(1),
+            &mut |v, u| cost(source.get_by_left(&v).unwrap(), target.get_by_left(&u).unwrap()),
+            &mut context,
+        );
+        let best = best
+            .into_iter()
+            .take(top)
+            .map(|(cost, pairs)| (translate(source, target, pairs.into_iter()).collect(), cost))
+            .collect();
+        Optimum { best, interrupted }
+    }
+}
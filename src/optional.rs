@@ -0,0 +1,180 @@
+use crate::enumerator::Objective;
+use crate::environment::Environment;
+use crate::search::{Context, Interrupt, Limit};
+use crate::statement::{Constraint, Variable};
+use crate::wrapper::{translate, Builder, Isoperm, Lookup, Permutation, Var};
+use itertools::Itertools;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter::zip;
+
+/// An optional constraint with its weight.
+pub type Weighted<R, U, V = U, W = U> = (R, Vec<Var<U, V, W>>, f64);
+// The optional constraints of a side with the translation of its variables.
+type Given<'t, R, U, V, W> = (Vec<Weighted<R, U, V, W>>, &'t Lookup<U, V, W>);
+
+// The optional constraints of each side with their weights, from the heaviest.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Optional {
+    source: Vec<(Constraint, f64)>,
+    target: Vec<(Constraint, f64)>,
+}
+
+/// # The ranking struct.
+/// The permutations of the largest preserved weights found by `Isoperm::rank`.
+pub struct Ranking<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The permutations with their preserved weights, from the largest weight.
+    /// Permutations of the same weight are in the order they are enumerated.
+    pub ranked: Vec<(Permutation<'t, U, V, W>, f64)>,
+    /// The reason why the enumeration was cut short, or `None` if every
+    /// permutation is ranked.
+    pub interrupted: Option<Interrupt>,
+}
+
+impl<U, V, W> Isoperm<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Rank the permutations by the total weight of the optional constraints
+    /// they preserve, and returns the given number of the first ones. A pair of
+    /// source and target optional constraints is preserved if the permutation
+    /// maps one to the other, and weighs the smaller of their weights. Each
+    /// optional constraint is preserved at most once, and they are paired from
+    /// the heaviest, which is exact unless they use expression variables. The
+    /// search prunes the subtrees whose weights cannot be among the largest
+    /// found so far, including the orderings of unconfined variables, and is
+    /// bounded by the limit as a whole, in which case the permutations found
+    /// are ranked. The enumeration of the instance is left as it is.
+    pub fn rank(&self, top: usize, limit: Limit) -> Ranking<'_, U, V, W> {
+        let (source, target) = (&self.source_translation, &self.target_translation);
+        let mut context = Context::new(&limit, None, None);
+        let (ranked, interrupted) =
+            self.permutation.minimize(top.max(1), &mut &self.optional, &mut context);
+        let ranked = ranked
+            .into_iter()
+            .take(top)
+            .map(|(score, pairs)| (translate(source, target, pairs.into_iter()).collect(), -score))
+            .collect();
+        Ranking { ranked, interrupted }
+    }
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Eq + Hash,
+    T: Eq + Hash,
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Adds optional constraints with weights to each side, which are given as
+    /// the constraints are, but are left out of the matching of the
+    /// constraints, so that the permutations are the same. Building returns an
+    /// error if some optional constraint uses an undeclared variable.
+    pub fn optional<O>(mut self, source: O, target: O) -> Self
+    where
+        O: IntoIterator<Item = Weighted<R, U, V, W>>,
+    {
+        self.source_optional.extend(source);
+        self.target_optional.extend(target);
+        self
+    }
+}
+
+impl Optional {
+    // Transform the optional constraints of each side, and sort them from the
+    // heaviest.
+    pub(crate) fn new<R, U, V, W>(
+        (source, source_translation): Given<'_, R, U, V, W>,
+        (target, target_translation): Given<'_, R, U, V, W>,
+    ) -> Result<Self, String>
+    where
+        R: Eq + Hash,
+        U: Eq + Hash + PartialEq,
+        V: Eq + Hash + PartialEq,
+        W: Eq + Hash + PartialEq,
+    {
+        let mut record = HashMap::new();
+        let mut transform = |optional: Vec<Weighted<R, U, V, W>>, translation| {
+            let (constraints, weights): (Vec<_>, Vec<_>) =
+                optional.into_iter().map(|(r, vs, weight)| ((r, vs), weight)).unzip();
+            let constraints =
+                Isoperm::transform_constraints(constraints, translation, &mut record)?;
+            let weighted = zip(constraints, weights);
+            Ok::<_, String>(weighted.sorted_by(|a, b| b.1.total_cmp(&a.1)).collect())
+        };
+        Ok(Optional {
+            source: transform(source, source_translation)?,
+            target: transform(target, target_translation)?,
+        })
+    }
+
+    // Returns the total weight of the pairs of optional constraints preserved
+    // under the bindings.
+    fn preserved(&self, environment: &Environment) -> f64 {
+        let mut used = vec![false; self.target.len()];
+        self.source
+            .iter()
+            .filter_map(|(c, weight)| {
+                let argument = c.argument().iter().map(|v| match v {
+                    Variable::Expr(_) => Some(*v),
+                    _ => environment.target_of(v),
+                });
+                let argument = argument.collect::<Option<Vec<_>>>()?;
+                let t = (0..self.target.len()).find(|&t| {
+                    let other = &self.target[t].0;
+                    !used[t]
+                        && other.signature() == c.signature()
+                        && other.argument().len() == argument.len()
+                        && zip(other.argument(), &argument).all(|pair| match pair {
+                            (Variable::Expr(_), _) | (_, Variable::Expr(_)) => true,
+                            (u, w) => u == w,
+                        })
+                })?;
+                used[t] = true;
+                Some(weight.min(self.target[t].1))
+            })
+            .sum()
+    }
+
+    // Returns an upper bound of the weights preserved under the bindings and
+    // any more of them. A source optional constraint can still be preserved
+    // by a target one if each pair of their arguments is bound to each other,
+    // or can be.
+    fn reachable(&self, environment: &Environment) -> f64 {
+        self.source
+            .iter()
+            .map(|(c, weight)| {
+                let reachable = self.target.iter().filter(|(other, _)| {
+                    other.signature() == c.signature()
+                        && other.argument().len() == c.argument().len()
+                        && zip(c.argument(), other.argument()).all(|pair| match pair {
+                            (Variable::Expr(_), _) | (_, Variable::Expr(_)) => true,
+                            (v, u) => environment.compatible(v, u),
+                        })
+                });
+                reachable.map(|(_, other)| weight.min(*other)).fold(0.0, f64::max)
+            })
+            .sum()
+    }
+}
+
+// The preserved weights are negated to be minimized.
+impl Objective for &Optional {
+    fn bind(&mut self, environment: &Environment, _: &[Variable]) -> f64 {
+        -self.reachable(environment)
+    }
+
+    fn unbind(&mut self) {}
+
+    fn score(&self, environment: &Environment) -> f64 {
+        -self.preserved(environment)
+    }
+}
@@ -0,0 +1,324 @@
+use crate::enumerator::{Prefix, StatementEnumerator};
+use crate::search::{Context, Limit};
+use crate::statement::Variable;
+use crate::wrapper::{translate, Isoperm, Lookup, Permutation};
+use std::collections::{HashMap, VecDeque};
+use std::hash::Hash;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+
+// The number of permutations that can wait in the channel before the workers
+// block, and that can be held back for each subtree if the order is kept.
+const CAPACITY: usize = 1024;
+
+// The bindings of a permutation.
+type Pairs = Vec<(Variable, Variable)>;
+// A permutation found in a subtree, or `None` once the subtree is searched.
+type Message = (usize, Option<Pairs>);
+
+// The subtree whose permutations are taken next if the order is kept. The
+// workers wait for it to advance before they search too far ahead of it.
+struct Window {
+    current: Mutex<usize>,
+    advanced: Condvar,
+    // The number of subtrees that can be searched from the current one on.
+    width: usize,
+}
+
+// The subtrees of a search, which are taken by workers in order.
+struct Work {
+    base: StatementEnumerator,
+    prefixes: Vec<Prefix>,
+    next: AtomicUsize,
+    stop: Arc<AtomicBool>,
+    window: Option<Arc<Window>>,
+}
+
+impl Work {
+    // Search the remaining subtrees, and pass each permutation to the visitor
+    // until it returns `false`, or the search is stopped.
+    fn run(&self, mut visitor: impl FnMut(usize, Option<Pairs>) -> bool) {
+        let limit = Limit::new().cancellation(self.stop.clone());
+        loop {
+            let index = self.next.fetch_add(1, Ordering::Relaxed);
+            let Some(prefix) = self.prefixes.get(index) else { return };
+            if !self.wait(|current, width| index < current + width) {
+                return;
+            }
+            let mut subtree = self.base.subtree(prefix);
+            let mut context = Context::new(&limit, None, None);
+            let mut found = 0;
+            loop {
+                match subtree.next(&mut context) {
+                    Ok(true) => {
+                        // Hold back no more than the capacity of a subtree
+                        // whose permutations are not taken yet.
+                        found += 1;
+                        if found > CAPACITY && !self.wait(|current, _| index == current) {
+                            return;
+                        }
+                        if !visitor(index, Some(subtree.environment().pairs().collect())) {
+                            return;
+                        }
+                    }
+                    Ok(false) => break,
+                    Err(_) => return,
+                }
+            }
+            if !visitor(index, None) {
+                return;
+            }
+        }
+    }
+
+    // Wait until the current subtree and the width of the window are ready, if
+    // the order is kept. Returns `false` if the search is stopped.
+    fn wait(&self, ready: impl Fn(usize, usize) -> bool) -> bool {
+        let Some(window) = &self.window else { return true };
+        let mut current = window.current.lock().unwrap();
+        while !ready(*current, window.width) && !self.stop.load(Ordering::Relaxed) {
+            current = window.advanced.wait(current).unwrap();
+        }
+        !self.stop.load(Ordering::Relaxed)
+    }
+}
+
+/// # The parallel search struct.
+/// The search tree is split into subtrees under the first stages of the
+/// search, which are searched from the start by a number of threads. The
+/// enumeration of the `Isoperm` instance is left as it is.
+pub struct Parallel<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source: &'t Lookup<U, V, W>,
+    target: &'t Lookup<U, V, W>,
+    perm: &'t StatementEnumerator,
+    threads: usize,
+    depth: usize,
+    ordered: bool,
+}
+
+impl<U, V, W> Isoperm<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Search the permutations in parallel. By default, the search tree is
+    /// split by the choices for the first constraint, and searched by as many
+    /// threads as the available parallelism.
+    pub fn parallel(&self) -> Parallel<'_, U, V, W> {
+        Parallel {
+            source: &self.source_translation,
+            target: &self.target_translation,
+            perm: &self.permutation,
+            threads: thread::available_parallelism().map_or(1, NonZeroUsize::get),
+            depth: 1,
+            ordered: false,
+        }
+    }
+}
+
+impl<'t, U, V, W> Parallel<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Set the number of threads, which is at least one.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Split the search tree by the choices for the given number of
+    /// constraints matched first. Deeper splits make more subtrees of less
+    /// work each.
+    pub fn depth(mut self, depth: usize) -> Self {
+        self.depth = depth;
+        self
+    }
+
+    /// Keep the permutations in the order of the sequential search. The
+    /// permutations of a subtree are held back until the subtrees before it
+    /// are searched, where the threads wait rather than search too far ahead,
+    /// so that a bounded number of permutations is held back.
+    pub fn ordered(mut self, ordered: bool) -> Self {
+        self.ordered = ordered;
+        self
+    }
+
+    /// Returns the number of permutations.
+    pub fn count(&self) -> usize {
+        let work = self.work();
+        thread::scope(|scope| {
+            let workers = (0..self.threads)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut count = 0;
+                        work.run(|_, pairs| {
+                            count += pairs.is_some() as usize;
+                            true
+                        });
+                        count
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers.into_iter().map(|worker| worker.join().unwrap()).sum()
+        })
+    }
+
+    /// Check if there is any permutation. The other threads stop as soon as
+    /// one of them finds a permutation.
+    pub fn exists(&self) -> bool {
+        let work = self.work();
+        thread::scope(|scope| {
+            (0..self.threads).for_each(|_| {
+                scope.spawn(|| {
+                    work.run(|_, pairs| {
+                        if pairs.is_some() {
+                            work.stop.store(true, Ordering::Relaxed);
+                        }
+                        pairs.is_none()
+                    })
+                });
+            });
+        });
+        work.stop.load(Ordering::Relaxed)
+    }
+
+    /// Returns the iterator of all permutations, which are found by threads in
+    /// the background. The threads stop once the iterator is dropped.
+    pub fn result(self) -> ParallelPermutations<'t, U, V, W> {
+        let mut work = self.work();
+        let window =
+            Window { current: Mutex::new(0), advanced: Condvar::new(), width: self.threads };
+        work.window = self.ordered.then(|| Arc::new(window));
+        let work = Arc::new(work);
+        let (sender, receiver) = sync_channel(CAPACITY);
+        let workers = (0..self.threads)
+            .map(|_| {
+                let (work, sender): (_, SyncSender<Message>) = (work.clone(), sender.clone());
+                thread::spawn(move || work.run(|index, pairs| sender.send((index, pairs)).is_ok()))
+            })
+            .collect();
+        ParallelPermutations {
+            source: self.source,
+            target: self.target,
+            receiver: Some(receiver),
+            stop: work.stop.clone(),
+            workers,
+            ordered: self.ordered.then(|| (0, work.prefixes.len())),
+            pending: HashMap::new(),
+            window: work.window.clone(),
+        }
+    }
+
+    fn work(&self) -> Work {
+        Work {
+            base: self.perm.clone(),
+            prefixes: self.perm.split(self.depth),
+            next: AtomicUsize::new(0),
+            stop: Arc::new(AtomicBool::new(false)),
+            window: None,
+        }
+    }
+}
+
+/// The iterator of permutations found in parallel.
+pub struct ParallelPermutations<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source: &'t Lookup<U, V, W>,
+    target: &'t Lookup<U, V, W>,
+    receiver: Option<Receiver<Message>>,
+    stop: Arc<AtomicBool>,
+    workers: Vec<JoinHandle<()>>,
+    // The subtree to take permutations from next and the number of subtrees,
+    // if the order is kept.
+    ordered: Option<(usize, usize)>,
+    // The permutations held back, and whether the subtrees are searched.
+    pending: HashMap<usize, (VecDeque<Pairs>, bool)>,
+    window: Option<Arc<Window>>,
+}
+
+impl<'t, U, V, W> ParallelPermutations<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    // Returns the bindings of the next permutation.
+    fn next_pairs(&mut self) -> Option<Pairs> {
+        let receiver = self.receiver.as_ref()?;
+        let Some((current, length)) = &mut self.ordered else {
+            return receiver.iter().find_map(|(_, pairs)| pairs);
+        };
+        while current < length {
+            let (queue, done) = self.pending.entry(*current).or_default();
+            if let Some(pairs) = queue.pop_front() {
+                return Some(pairs);
+            }
+            if *done {
+                self.pending.remove(current);
+                *current += 1;
+                if let Some(window) = &self.window {
+                    *window.current.lock().unwrap() = *current;
+                    window.advanced.notify_all();
+                }
+                continue;
+            }
+            let (index, pairs) = receiver.recv().ok()?;
+            let (queue, done) = self.pending.entry(index).or_default();
+            match pairs {
+                Some(pairs) => queue.push_back(pairs),
+                None => *done = true,
+            }
+        }
+        None
+    }
+}
+
+impl<'t, U, V, W> Iterator for ParallelPermutations<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    type Item = Permutation<'t, U, V, W>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let pairs = self.next_pairs()?;
+        Some(translate(self.source, self.target, pairs.into_iter()).collect())
+    }
+}
+
+impl<'t, U, V, W> Drop for ParallelPermutations<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    // Stop the workers, and unblock those waiting on the channel or on the
+    // window.
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(window) = &self.window {
+            let _current = window.current.lock().unwrap();
+            window.advanced.notify_all();
+        }
+        self.receiver = None;
+        self.workers.drain(..).for_each(|worker| {
+            let _ = worker.join();
+        });
+    }
+}
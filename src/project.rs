@@ -0,0 +1,99 @@
+use crate::enumerator::StatementEnumerator;
+use crate::search::{Context, Interrupt, Limit};
+use crate::statement::Variable;
+use crate::wrapper::{Isoperm, Lookup, Var};
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+
+/// The target local variables that a source local variable is mapped to.
+pub type Support<'t, U, V = U, W = U> = HashSet<&'t Var<U, V, W>>;
+
+/// # The projection struct.
+/// The projection of the permutations on each source local variable, which is
+/// the set of target local variables it is mapped to by some permutation.
+pub struct Projection<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The target local variables of each source local variable, which are
+    /// empty if there is no permutation.
+    pub supports: HashMap<&'t Var<U, V, W>, Support<'t, U, V, W>>,
+    /// The reason why the search was cut short, or `None` if the supports are
+    /// complete. Otherwise, they hold the pairs found so far.
+    pub interrupted: Option<Interrupt>,
+}
+
+impl<U, V, W> Isoperm<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Find the supports of the source local variables. Each pair of a source
+    /// and a target local is searched for a single permutation that maps one to
+    /// the other, unless it is already in a permutation found, so that the
+    /// permutations are never enumerated. The search is bounded by the limit as
+    /// a whole, and the enumeration of the instance is left as it is.
+    pub fn project(&self, limit: Limit) -> Projection<'_, U, V, W> {
+        let (source, target) = (&self.source_translation, &self.target_translation);
+        let local = |lookup: &Lookup<U, V, W>| {
+            let local = lookup.left_values().filter(|v| matches!(v, Variable::Local(_)));
+            local.copied().collect::<Vec<_>>()
+        };
+        let (source_locals, target_locals) = (local(source), local(target));
+        let mut found: HashSet<(Variable, Variable)> = HashSet::new();
+        let mut context = Context::new(&limit, None, None);
+        let interrupted = self.witness(&mut found, &source_locals, &target_locals, &mut context);
+        let mut supports: HashMap<_, Support<_, _, _>> = source_locals
+            .iter()
+            .map(|v| (source.get_by_left(v).unwrap(), HashSet::new()))
+            .collect();
+        found.into_iter().for_each(|(v, u)| {
+            let support = supports.get_mut(source.get_by_left(&v).unwrap()).unwrap();
+            support.insert(target.get_by_left(&u).unwrap());
+        });
+        Projection { supports, interrupted: interrupted.err() }
+    }
+
+    // Collect the pairs of locals in some permutation, by searching for a
+    // witness of each pair not yet found.
+    fn witness(
+        &self,
+        found: &mut HashSet<(Variable, Variable)>,
+        source_locals: &[Variable],
+        target_locals: &[Variable],
+        context: &mut Context,
+    ) -> Result<(), Interrupt> {
+        let mut base = self.permutation.clone();
+        base.restart();
+        if !base.next_core(context)? {
+            return Ok(());
+        }
+        // The locals left unbound by the groups in a witness are mapped to
+        // each other in every order. They depend on the witness, as locals
+        // facing expressions are left unbound too.
+        let record = |perm: &StatementEnumerator, found: &mut HashSet<_>| {
+            let pairs = perm.environment().pairs();
+            found.extend(pairs.filter(|(v, _)| matches!(v, Variable::Local(_))));
+            perm.free().for_each(|(source, target)| {
+                source.iter().for_each(|&v| found.extend(target.iter().map(|&u| (v, u))));
+            });
+        };
+        record(&base, found);
+        for &v in source_locals {
+            for &u in target_locals {
+                if found.contains(&(v, u)) {
+                    continue;
+                }
+                if let Some(mut pinned) = base.pinned(&v, &u) {
+                    if pinned.next_core(context)? {
+                        record(&pinned, found);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
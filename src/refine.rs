@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::iter::zip;
+
+use itertools::Itertools;
+
+use crate::statement::{fingerprint, Constraint, ConstraintPair, Variable};
+
+// The colours of the source and target variables, indexed by their numbers.
+pub(crate) type Colouring = (Vec<u32>, Vec<u32>);
+
+// Refine the colours of the variables for at most the given number of rounds,
+// starting from colours that tell apart the types of locals and the pairs of
+// globals. In each round, a local is told apart by the groups and positions
+// of its occurrences, and the colours of the other arguments there. Only the
+// positions where no constraint of a group on either side has an expression
+// are considered, as those arguments must be bound to each other. Returns
+// `None` if the numbers of locals of some colour differ on the two sides, in
+// which case there is no permutation.
+pub(crate) fn refine<K: Hash + Ord>(
+    group: &[ConstraintPair],
+    (source, target): (&[Variable], &[Variable]),
+    initial: (Vec<K>, Vec<K>),
+    rounds: usize,
+) -> Option<Colouring> {
+    let exact = group
+        .iter()
+        .map(|(s, t)| {
+            let arity = s.first().map_or(0, |c| c.argument().len());
+            (0..arity)
+                .filter(|&p| {
+                    s.iter().chain(t).all(|c| !matches!(c.argument()[p], Variable::Expr(_)))
+                })
+                .collect_vec()
+        })
+        .collect_vec();
+    let (mut source_colour, mut target_colour) = relabel(initial.0, initial.1);
+    let mut count = 0;
+    for _ in 0..rounds {
+        if !balanced((source, &source_colour), (target, &target_colour)) {
+            return None;
+        }
+        let distinct = source_colour.iter().chain(&target_colour).unique().count();
+        if distinct == count {
+            break;
+        }
+        count = distinct;
+        let signature = |colour: &[u32], side: fn(&ConstraintPair) -> &Vec<Constraint>| {
+            let mut occurrence = vec![Vec::new(); colour.len()];
+            zip(group, &exact).enumerate().for_each(|(g, (pair, exact))| {
+                side(pair).iter().for_each(|c| {
+                    let neighbour = exact.iter().map(|&p| colour[c.argument()[p].index()]);
+                    let hash = fingerprint(&(g, neighbour.collect_vec()));
+                    exact
+                        .iter()
+                        .filter(|&&p| matches!(c.argument()[p], Variable::Local(_)))
+                        .for_each(|&p| occurrence[c.argument()[p].index()].push((hash, p)));
+                });
+            });
+            zip(colour, occurrence)
+                .map(|(&c, mut o)| {
+                    o.sort_unstable();
+                    (c, o)
+                })
+                .collect_vec()
+        };
+        let source_signature = signature(&source_colour, |(s, _)| s);
+        let target_signature = signature(&target_colour, |(_, t)| t);
+        (source_colour, target_colour) = relabel(source_signature, target_signature);
+    }
+    balanced((source, &source_colour), (target, &target_colour))
+        .then_some((source_colour, target_colour))
+}
+
+// Number the keys of both sides densely in their order.
+fn relabel<K: Hash + Ord>(source: Vec<K>, target: Vec<K>) -> Colouring {
+    let colour: HashMap<_, _> = source
+        .iter()
+        .chain(&target)
+        .sorted()
+        .dedup()
+        .enumerate()
+        .map(|(i, k)| (k, i as u32))
+        .collect();
+    (source.iter().map(|k| colour[k]).collect(), target.iter().map(|k| colour[k]).collect())
+}
+
+// Check if each colour is taken by the same number of locals on both sides.
+fn balanced(
+    (source, source_colour): (&[Variable], &[u32]),
+    (target, target_colour): (&[Variable], &[u32]),
+) -> bool {
+    let histogram = |variables: &[Variable], colour: &[u32]| {
+        zip(variables, colour)
+            .filter(|(v, _)| matches!(v, Variable::Local(_)))
+            .map(|(_, &c)| c)
+            .counts()
+    };
+    histogram(source, source_colour) == histogram(target, target_colour)
+}
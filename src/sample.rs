@@ -0,0 +1,193 @@
+use crate::enumerator::StatementEnumerator;
+use crate::search::{Context, Limit};
+use crate::statement::Variable;
+use crate::wrapper::{translate, Isoperm, Lookup, Permutation};
+use std::hash::Hash;
+use std::iter::zip;
+
+// The bindings of a permutation.
+type Pairs = Vec<(Variable, Variable)>;
+// The source and target locals of each type left unbound by the groups.
+type Free = Vec<(Vec<Variable>, Vec<Variable>)>;
+
+/// # The random source trait.
+/// A random source is supplied by the caller, so that any generator and seed
+/// can be used.
+pub trait Random {
+    /// Returns the next random number, whose bits are uniformly distributed.
+    fn next_u64(&mut self) -> u64;
+
+    /// Returns a random number uniformly distributed below the bound, which
+    /// must be positive.
+    fn below(&mut self, bound: usize) -> usize {
+        let bound = bound as u64;
+        // Reject the numbers below the remainder, which would be more likely.
+        let threshold = bound.wrapping_neg() % bound;
+        loop {
+            let number = self.next_u64();
+            if number >= threshold {
+                return (number % bound) as usize;
+            }
+        }
+    }
+
+    /// Returns a random number uniformly distributed in `[0, 1)`.
+    fn unit(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// # The sampler struct.
+/// A sampler draws permutations at random. If the permutations of the
+/// constraint groups are no more than the budget, they are enumerated once,
+/// and the samples are exactly uniform. Otherwise, each sample is drawn by
+/// walking down random paths of the search tree, and accepting a path by its
+/// weight, the product of the numbers of choices along it and of the orderings
+/// of the locals it leaves unbound, relative to the largest weight seen so
+/// far. The samples are then uniform once the largest weight has been seen.
+/// The orderings of the locals left unbound by the constraint groups are
+/// always drawn uniformly. A permutation found by several matchings of the
+/// constraints with expressions is drawn as often as it is enumerated.
+pub struct Sampler<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source: &'t Lookup<U, V, W>,
+    target: &'t Lookup<U, V, W>,
+    perm: StatementEnumerator,
+    // The permutations of the groups with the locals each leaves unbound, if
+    // they are enumerated, and the running totals of their orderings.
+    cores: Option<Vec<(Pairs, Free)>>,
+    total: Vec<u128>,
+    bound: f64,
+    attempts: usize,
+}
+
+impl<U, V, W> Isoperm<U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns a sampler of the permutations, which enumerates the
+    /// permutations of the constraint groups if there are no more than the
+    /// budget. The enumeration of the instance is left as it is.
+    pub fn sampler(&self, budget: usize) -> Sampler<'_, U, V, W> {
+        let mut perm = self.permutation.clone();
+        perm.restart();
+        let limit = Limit::new();
+        let mut context = Context::new(&limit, None, None);
+        let mut cores: Vec<(Pairs, Free)> = Vec::new();
+        while cores.len() <= budget && perm.next_core(&mut context).unwrap_or(false) {
+            cores.push((perm.environment().pairs().collect(), perm.free().collect()));
+        }
+        let total = cores
+            .iter()
+            .scan(0u128, |total, (_, free)| {
+                *total = total.saturating_add(orderings(free));
+                Some(*total)
+            })
+            .collect();
+        Sampler {
+            source: &self.source_translation,
+            target: &self.target_translation,
+            cores: (cores.len() <= budget).then_some(cores),
+            total,
+            perm,
+            bound: 0.0,
+            attempts: 10000,
+        }
+    }
+}
+
+impl<'t, U, V, W> Sampler<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Set the number of paths walked for a sample before giving up, if the
+    /// permutations are not enumerated.
+    pub fn attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    /// Check if the permutations of the constraint groups are enumerated, in
+    /// which case the samples are exactly uniform.
+    pub fn exact(&self) -> bool {
+        self.cores.is_some()
+    }
+
+    /// Returns the number of permutations, saturated, or `None` if the
+    /// permutations are not enumerated.
+    pub fn count(&self) -> Option<u128> {
+        self.cores.as_ref()?;
+        Some(self.total.last().copied().unwrap_or(0))
+    }
+
+    /// Draw a permutation, or returns `None` if there are no permutations, or
+    /// none is accepted within the attempts.
+    pub fn sample(&mut self, random: &mut impl Random) -> Option<Permutation<'t, U, V, W>> {
+        let (core, free) = match &self.cores {
+            Some(cores) if cores.is_empty() => return None,
+            Some(cores) => {
+                // Draw a core by the number of its orderings.
+                let drawn = below(random, *self.total.last().unwrap());
+                cores[self.total.partition_point(|&total| total <= drawn)].clone()
+            }
+            None => (0..self.attempts).find_map(|_| {
+                let weight = self.perm.probe(&mut |bound| random.below(bound))?;
+                let free: Free = self.perm.free().collect();
+                let weight = weight * orderings(&free) as f64;
+                self.bound = self.bound.max(weight);
+                (random.unit() * self.bound < weight)
+                    .then(|| (self.perm.environment().pairs().collect(), free))
+            })?,
+        };
+        let free = free.into_iter().flat_map(|(source, mut target)| {
+            // Shuffle the targets.
+            (1..target.len()).rev().for_each(|i| target.swap(i, random.below(i + 1)));
+            zip(source, target)
+        });
+        Some(translate(self.source, self.target, core.into_iter().chain(free)).collect())
+    }
+
+    /// Estimate the number of permutations by walking down the given number of
+    /// random paths of the search tree, where each path that matches every
+    /// group stands for the product of the numbers of choices along it. The
+    /// estimate is unbiased, and it is exact if the permutations are
+    /// enumerated.
+    pub fn estimate(&mut self, random: &mut impl Random, probes: usize) -> f64 {
+        if let Some(count) = self.count() {
+            return count as f64;
+        }
+        let total: f64 = (0..probes)
+            .filter_map(|_| {
+                let weight = self.perm.probe(&mut |bound| random.below(bound))?;
+                Some(weight * orderings(&self.perm.free().collect()) as f64)
+            })
+            .sum();
+        total / probes.max(1) as f64
+    }
+}
+
+// Returns a random number uniformly distributed below the bound, which must be
+// positive, as `Random::below` does.
+fn below(random: &mut impl Random, bound: u128) -> u128 {
+    let threshold = bound.wrapping_neg() % bound;
+    loop {
+        let number = (random.next_u64() as u128) << 64 | random.next_u64() as u128;
+        if number >= threshold {
+            return number % bound;
+        }
+    }
+}
+
+// Returns the number of orderings of the free locals, saturated.
+fn orderings(free: &Free) -> u128 {
+    let factorial = |n: usize| (1..=n as u128).fold(1u128, u128::saturating_mul);
+    free.iter().map(|(source, _)| factorial(source.len())).fold(1, u128::saturating_mul)
+}
@@ -0,0 +1,167 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// Number of steps between two checks of the clock and the cancellation flag.
+const CHECK_INTERVAL: u64 = 256;
+
+/// # The search limit struct.
+/// A limit bounds the effort spent on finding the next permutation. Each call
+/// to the iterator starts with a fresh budget of steps and time, and gives up
+/// once the budget is exhausted or the cancellation flag is raised.
+#[derive(Clone, Debug, Default)]
+pub struct Limit {
+    steps: Option<u64>,
+    time: Option<Duration>,
+    cancellation: Option<Arc<AtomicBool>>,
+}
+
+impl Limit {
+    /// Create a limit that never interrupts the search.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of search steps taken to find the next permutation.
+    pub fn steps(mut self, steps: u64) -> Self {
+        self.steps = Some(steps);
+        self
+    }
+
+    /// Limit the wall-clock time spent to find the next permutation.
+    pub fn timeout(mut self, time: Duration) -> Self {
+        self.time = Some(time);
+        self
+    }
+
+    /// Interrupt the search once the shared flag is set to `true`.
+    pub fn cancellation(mut self, flag: Arc<AtomicBool>) -> Self {
+        self.cancellation = Some(flag);
+        self
+    }
+}
+
+/// # The search statistics struct.
+/// Statistics are accumulated over all searches made by an iterator once
+/// collection is enabled. Groups are numbered in the order they are searched,
+/// with the constraint groups coming before the groups of unconfined
+/// variables.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Statistics {
+    /// The number of candidate bindings visited.
+    pub nodes: u64,
+    /// The number of bindings undone in each group.
+    pub backtracks: Vec<u64>,
+    /// The number of bindings committed to the environment.
+    pub committed: u64,
+    /// The number of bindings rejected due to conflicts.
+    pub rejected: u64,
+    /// The number of times the search stepped back over some groups.
+    pub backjumps: u64,
+    /// The number of times a group was skipped by a remembered failure.
+    pub nogoods: u64,
+    /// The time spent in matching constraint groups.
+    pub group_time: Duration,
+    /// The time spent in matching unconfined variables.
+    pub unconfined_time: Duration,
+}
+
+/// # The search observer trait.
+/// An observer is notified whenever a group pushes a binding onto its stack or
+/// pops one from it, which allows recording search traces. Groups are numbered
+/// as in `Statistics`, and the depth is the size of the stack of the group
+/// with the binding included.
+pub trait Observer {
+    /// Called after a binding is committed by a group.
+    fn push(&mut self, _group: usize, _depth: usize) {}
+
+    /// Called after a binding is undone by a group.
+    fn pop(&mut self, _group: usize, _depth: usize) {}
+}
+
+/// The reason why a search was cut short.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Interrupt {
+    /// The step limit was reached.
+    Steps,
+    /// The time limit was reached.
+    Timeout,
+    /// The cancellation flag was raised.
+    Cancelled,
+}
+
+// The bookkeeping of a single search.
+pub(crate) struct Context<'l> {
+    limit: &'l Limit,
+    steps: u64,
+    deadline: Option<Instant>,
+    statistics: Option<&'l mut Statistics>,
+    observer: Option<&'l mut dyn Observer>,
+    group: usize,
+}
+
+impl<'l> Context<'l> {
+    pub(crate) fn new(
+        limit: &'l Limit,
+        statistics: Option<&'l mut Statistics>,
+        observer: Option<&'l mut dyn Observer>,
+    ) -> Self {
+        let deadline = limit.time.map(|time| Instant::now() + time);
+        Self { limit, steps: 0, deadline, statistics, observer, group: 0 }
+    }
+
+    // Set the group that the following events belong to.
+    pub(crate) fn enter(&mut self, group: usize) {
+        self.group = group;
+    }
+
+    // Update the statistics if they are collected.
+    pub(crate) fn record(&mut self, update: impl FnOnce(&mut Statistics)) {
+        if let Some(statistics) = self.statistics.as_deref_mut() {
+            update(statistics);
+        }
+    }
+
+    pub(crate) fn push(&mut self, depth: usize) {
+        self.record(|statistics| statistics.committed += 1);
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.push(self.group, depth);
+        }
+    }
+
+    pub(crate) fn pop(&mut self, depth: usize) {
+        let group = self.group;
+        self.record(|statistics| {
+            if statistics.backtracks.len() <= group {
+                statistics.backtracks.resize(group + 1, 0);
+            }
+            statistics.backtracks[group] += 1;
+        });
+        if let Some(observer) = self.observer.as_deref_mut() {
+            observer.pop(group, depth);
+        }
+    }
+
+    // Account for one search step, and check if the search should stop.
+    pub(crate) fn tick(&mut self) -> Result<(), Interrupt> {
+        self.check()?;
+        self.steps += 1;
+        Ok(())
+    }
+
+    // Check if the search should stop, without taking a step.
+    pub(crate) fn check(&self) -> Result<(), Interrupt> {
+        if self.limit.steps.is_some_and(|steps| self.steps >= steps) {
+            return Err(Interrupt::Steps);
+        }
+        if self.steps.is_multiple_of(CHECK_INTERVAL) {
+            if self.limit.cancellation.as_ref().is_some_and(|flag| flag.load(Ordering::Relaxed)) {
+                return Err(Interrupt::Cancelled);
+            }
+            if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                return Err(Interrupt::Timeout);
+            }
+        }
+        Ok(())
+    }
+}
@@ -1,9 +1,12 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::hash::{Hash, Hasher};
 
 use itertools::Itertools;
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+use crate::config::GroupOrder;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub(crate) enum Variable {
     Expr(usize),
     Global(usize),
@@ -11,6 +14,12 @@ pub(crate) enum Variable {
 }
 
 impl Variable {
+    pub(crate) fn index(&self) -> usize {
+        match *self {
+            Variable::Expr(i) | Variable::Global(i) | Variable::Local(i) => i,
+        }
+    }
+
     pub(crate) fn group_local_by_type<T: Eq + Hash>(
         variables: &HashMap<Variable, T>,
     ) -> HashMap<&T, Vec<Variable>> {
@@ -21,10 +30,16 @@ impl Variable {
                 _ => None,
             })
             .into_group_map()
+            .into_iter()
+            .map(|(t, mut v)| {
+                v.sort();
+                (t, v)
+            })
+            .collect()
     }
 }
 
-#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
 pub(crate) struct Constraint(usize, Vec<Variable>);
 
 impl Constraint {
@@ -52,14 +67,60 @@ impl Constraint {
     }
 }
 
+pub(crate) type ConstraintGroups<'t, T> = HashMap<(usize, Vec<&'t T>), Vec<Constraint>>;
+
 // Group constraints by their signatures and argument types.
 pub(crate) fn group_constraints<T: Eq + Hash>(
     constraints: Vec<Constraint>,
     variables: &HashMap<Variable, T>,
-) -> Result<HashMap<(usize, Vec<&T>), Vec<Constraint>>, String> {
+) -> Result<ConstraintGroups<'_, T>, String> {
     constraints
         .into_iter()
         .map(|c| c.argument_types(variables).map(|tys| ((c.signature(), tys), c)))
         .collect::<Result<Vec<_>, String>>()
         .map(|group| group.into_iter().into_group_map())
 }
+
+pub(crate) type ConstraintPair = (Vec<Constraint>, Vec<Constraint>);
+
+// Arrange matched constraint groups in the order to be searched. The groups
+// are expected to be sorted already, which breaks the ties.
+pub(crate) fn order_groups(
+    order: GroupOrder,
+    mut group: Vec<ConstraintPair>,
+) -> Vec<ConstraintPair> {
+    match order {
+        GroupOrder::Input => group,
+        GroupOrder::Smallest => {
+            group.sort_by_key(|(source, _)| source.len());
+            group
+        }
+        GroupOrder::Connected => {
+            let mut placed = HashSet::new();
+            let mut ordered = Vec::with_capacity(group.len());
+            while let Some((index, _)) = group.iter().enumerate().min_by_key(|(_, (source, _))| {
+                let shared = source
+                    .iter()
+                    .flat_map(Constraint::argument)
+                    .filter(|v| matches!(v, Variable::Local(_)))
+                    .unique()
+                    .filter(|v| placed.contains(*v))
+                    .count();
+                (std::cmp::Reverse(shared), source.len())
+            }) {
+                let (source, target) = group.remove(index);
+                placed.extend(source.iter().flat_map(Constraint::argument).copied());
+                ordered.push((source, target));
+            }
+            ordered
+        }
+    }
+}
+
+// Hash a value with fixed keys, so that the result is reproducible across
+// processes as long as the `Hash` implementation is.
+pub(crate) fn fingerprint<H: Hash + ?Sized>(value: &H) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
@@ -1,7 +1,16 @@
+use crate::config::{Config, GroupOrder, Strategy};
+use crate::sample::Random;
+use crate::search::{Interrupt, Limit, Observer};
 use crate::wrapper::Var::*;
 use crate::wrapper::{Isoperm, Var};
 use bimap::BiMap;
-use std::iter::once;
+use itertools::Itertools;
+use std::collections::{HashMap, HashSet};
+use std::iter::{once, zip};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 
 #[test]
 fn basic_test() {
@@ -42,3 +51,1372 @@ fn zero_local_test() {
             .unwrap();
     isoperm.result().take(5).for_each(|bind: BiMap<&Var<i32>, &Var<i32>>| println!("{:?}", bind));
 }
+
+type Bag = Vec<(&'static str, Vec<Var<i32>>)>;
+
+// A cycle of `n` locals related by `R`, plus `free` locals used by nothing.
+fn cycle(n: i32, free: i32) -> (Bag, HashMap<Var<i32>, bool>) {
+    let constraints = (0..n).map(|i| ("R", vec![Local(i), Local((i + 1) % n)])).collect();
+    let variables =
+        (0..n + free).map(|i| (Local(i), true)).chain(once((Global(0), false))).collect();
+    (constraints, variables)
+}
+
+fn cycle_isoperm(n: i32, free: i32) -> Isoperm<i32> {
+    let (source_constraints, source_variables) = cycle(n, free);
+    let (target_constraints, target_variables) = cycle(n, free);
+    Isoperm::new(source_constraints, source_variables, target_constraints, target_variables)
+        .unwrap()
+}
+
+fn key(v: &Var<i32>) -> (u8, i32) {
+    match *v {
+        Expr(i) => (0, i),
+        Global(i) => (1, i),
+        Local(i) => (2, i),
+    }
+}
+
+type Solution = Vec<((u8, i32), (u8, i32))>;
+
+fn canonical(bind: BiMap<&Var<i32>, &Var<i32>>) -> Solution {
+    bind.into_iter().map(|(s, t)| (key(s), key(t))).sorted().collect()
+}
+
+#[test]
+fn checkpoint_test() {
+    let expected: HashSet<_> = cycle_isoperm(4, 2).result().map(canonical).collect();
+    assert_eq!(expected.len(), 8);
+    let mut isoperm = cycle_isoperm(4, 2);
+    let mut found: Vec<_> = isoperm.result().take(3).map(canonical).collect();
+    let checkpoint = isoperm.checkpoint().to_string();
+    let mut resumed = cycle_isoperm(4, 2);
+    resumed.resume(&checkpoint.parse().unwrap()).unwrap();
+    found.extend(resumed.result().map(canonical));
+    assert_eq!(found.len(), expected.len());
+    assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected);
+    assert!(cycle_isoperm(5, 2).resume(&checkpoint.parse().unwrap()).is_err());
+    // Checkpoints do not depend on the order in which shared globals are
+    // paired by each instance.
+    let variables: HashMap<_, _> =
+        (0..4).map(|i| (Local(i), 1)).chain((0..4).map(|i| (Global(i), 0))).collect();
+    let constraints = vec![("R", vec![Global(0), Local(0)]), ("R", vec![Global(1), Local(1)])];
+    let shared = || {
+        Isoperm::new(constraints.clone(), variables.clone(), constraints.clone(), variables.clone())
+            .unwrap()
+    };
+    for _ in 0..20 {
+        let expected: HashSet<_> = shared().result().map(canonical).collect();
+        let mut isoperm = shared();
+        let mut found = isoperm.result().take(1).map(canonical).collect_vec();
+        let checkpoint = isoperm.checkpoint().to_string();
+        let mut resumed = shared();
+        resumed.resume(&checkpoint.parse().unwrap()).unwrap();
+        found.extend(resumed.result().map(canonical));
+        assert_eq!(found.len(), expected.len());
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected);
+    }
+}
+
+#[test]
+fn visitor_test() {
+    let expected: HashSet<_> = cycle_isoperm(4, 2).result().map(canonical).collect();
+    let mut isoperm = cycle_isoperm(4, 2);
+    let mut found = HashSet::new();
+    let visited = isoperm.result().for_each_solution(|view| {
+        assert_eq!(view.target_of(&Global(0)), Some(&Global(0)));
+        view.iter().for_each(|(s, t)| {
+            assert_eq!(view.target_of(s), Some(t));
+            assert_eq!(view.source_of(t), Some(s));
+        });
+        found.insert(view.iter().map(|(s, t)| (key(s), key(t))).sorted().collect_vec());
+        ControlFlow::<()>::Continue(())
+    });
+    assert_eq!(visited, Ok(None));
+    assert_eq!(found, expected);
+    let mut isoperm = cycle_isoperm(4, 2);
+    let mut permutation = isoperm.result();
+    let mut count = 0;
+    let stopped = permutation.for_each_solution(|view| {
+        count += 1;
+        view.target_of(&Local(0)).copied().map_or(ControlFlow::Continue(()), ControlFlow::Break)
+    });
+    assert!(matches!(stopped, Ok(Some(Local(_)))));
+    assert_eq!(count, 1);
+    assert_eq!(permutation.count(), expected.len() - 1);
+}
+
+#[test]
+fn symbolic_test() {
+    let expected: HashSet<_> = cycle_isoperm(4, 2).result().map(canonical).collect();
+    let mut isoperm = cycle_isoperm(4, 2);
+    let symbolic = isoperm.result().symbolic().collect_vec();
+    assert_eq!(symbolic.len(), 4);
+    assert!(symbolic.iter().all(|p| p.free.len() == 1 && p.free[0].0.len() == 2));
+    let expanded = symbolic.iter().flat_map(|p| p.expand()).map(canonical).collect_vec();
+    assert_eq!(expanded.len(), expected.len());
+    assert_eq!(expanded.into_iter().collect::<HashSet<_>>(), expected);
+    let mut isoperm = cycle_isoperm(4, 0);
+    let symbolic = isoperm.result().symbolic().collect_vec();
+    assert_eq!(symbolic.len(), 4);
+    assert!(symbolic.iter().all(|p| p.free.is_empty() && p.expand().count() == 1));
+}
+
+#[test]
+fn limit_test() {
+    let mut isoperm = cycle_isoperm(3, 5);
+    let mut result = isoperm.result().limit(Limit::new().steps(1));
+    assert!(result.next().is_none());
+    assert_eq!(result.interrupted(), Some(Interrupt::Steps));
+    let mut count = 0;
+    loop {
+        match result.try_next() {
+            Ok(Some(_)) => count += 1,
+            Ok(None) => break,
+            Err(interrupt) => assert_eq!(interrupt, Interrupt::Steps),
+        }
+    }
+    assert_eq!(count, 3 * 120);
+    let flag = Arc::new(AtomicBool::new(true));
+    let mut isoperm = cycle_isoperm(3, 5);
+    let mut result = isoperm.result().limit(Limit::new().cancellation(flag.clone()));
+    assert!(result.next().is_none());
+    assert_eq!(result.interrupted(), Some(Interrupt::Cancelled));
+    flag.store(false, Ordering::Relaxed);
+    assert!(result.next().is_some());
+    assert_eq!(result.interrupted(), None);
+    let mut result = isoperm.result().limit(Limit::new().timeout(Duration::ZERO));
+    assert_eq!(result.try_next(), Err(Interrupt::Timeout));
+}
+
+#[derive(Default)]
+struct Trace(Vec<(bool, usize, usize)>);
+
+impl Observer for Trace {
+    fn push(&mut self, group: usize, depth: usize) {
+        self.0.push((true, group, depth));
+    }
+
+    fn pop(&mut self, group: usize, depth: usize) {
+        self.0.push((false, group, depth));
+    }
+}
+
+#[test]
+fn statistics_test() {
+    let mut trace = Trace::default();
+    let mut isoperm = cycle_isoperm(4, 2);
+    let mut result = isoperm.result().collect_statistics().observe(&mut trace);
+    assert_eq!(result.by_ref().count(), 8);
+    let statistics = result.statistics().unwrap().clone();
+    assert_eq!(statistics.nodes, statistics.committed + statistics.rejected);
+    assert!(statistics.nodes > 0);
+    let pushes = trace.0.iter().filter(|(push, _, _)| *push).count() as u64;
+    assert_eq!(pushes, statistics.committed);
+    assert_eq!(trace.0.len() as u64 - pushes, statistics.backtracks.iter().sum::<u64>());
+    assert!(trace.0.iter().all(|&(_, group, depth)| group < 2 && depth > 0));
+}
+
+#[test]
+fn refinement_test() {
+    let variables: HashMap<Var<i32>, _> =
+        (0..4).map(|i| (Local(i), ())).chain(once((Expr(0), ()))).collect();
+    let star = vec![("R", vec![Local(0), Local(1)]), ("R", vec![Local(0), Local(2)])];
+    let path = vec![("R", vec![Local(0), Local(1)]), ("R", vec![Local(1), Local(2)])];
+    let run = |source: &Bag, target: &Bag, config: Config| {
+        let mut isoperm = Isoperm::with_config(
+            source.clone(),
+            variables.clone(),
+            target.clone(),
+            variables.clone(),
+            config,
+        )
+        .unwrap();
+        let mut result = isoperm.result().collect_statistics();
+        let count = result.by_ref().count();
+        (count, result.statistics().unwrap().nodes)
+    };
+    // The centre of the star has no counterpart in the path.
+    assert_eq!(run(&star, &path, Config::new()), (0, 0));
+    assert_eq!(run(&star, &path, Config::new().refinement(0)).0, 0);
+    // Arguments facing expressions are not refined.
+    let expression = vec![("R", vec![Expr(0), Local(1)]), ("R", vec![Local(1), Local(2)])];
+    let expected = run(&path, &expression, Config::new().refinement(0)).0;
+    assert!(expected > 0);
+    assert_eq!(run(&path, &expression, Config::new()).0, expected);
+}
+
+#[test]
+fn order_test() {
+    let (mut constraints, variables) = cycle(6, 1);
+    constraints.extend((0..6).map(|i| ("P", vec![Local(i), Local((i + 3) % 6)])));
+    let solutions = |order| {
+        let mut isoperm = Isoperm::with_config(
+            constraints.clone(),
+            variables.clone(),
+            constraints.clone(),
+            variables.clone(),
+            Config::new().order(order),
+        )
+        .unwrap();
+        isoperm.result().map(canonical).collect::<HashSet<_>>()
+    };
+    let expected = solutions(GroupOrder::Input);
+    assert_eq!(expected.len(), 6);
+    assert_eq!(solutions(GroupOrder::Smallest), expected);
+    assert_eq!(solutions(GroupOrder::Connected), expected);
+}
+
+#[test]
+fn duplicate_test() {
+    let variables: HashMap<Var<i32>, _> = (0..2).map(|i| (Local(i), ())).collect();
+    let source_constraints = vec![("R", vec![Local(0)]), ("R", vec![Local(1)])];
+    let target_constraints = vec![("R", vec![Local(0)]), ("R", vec![Local(0)])];
+    let mut isoperm =
+        Isoperm::new(source_constraints, variables.clone(), target_constraints, variables).unwrap();
+    assert_eq!(isoperm.result().count(), 0);
+    let variables: HashMap<Var<i32>, _> = (0..3).map(|i| (Local(i), ())).collect();
+    let constraints = vec![("R", vec![Local(0), Local(1)]), ("R", vec![Local(0), Local(1)])];
+    let mut isoperm =
+        Isoperm::new(constraints.clone(), variables.clone(), constraints, variables).unwrap();
+    assert_eq!(isoperm.result().count(), 1);
+}
+
+#[test]
+fn path_test() {
+    let n = 500;
+    let rename = |i: i32| (i * 7 + 3) % n;
+    let source_constraints: Bag = once(("S", vec![Local(0), Global(0)]))
+        .chain((1..n).map(|i| ("E", vec![Local(i - 1), Local(i)])))
+        .collect();
+    let target_constraints = source_constraints
+        .iter()
+        .map(|(r, vs)| {
+            (*r, vs.iter().map(|v| if let Local(i) = *v { Local(rename(i)) } else { *v }).collect())
+        })
+        .collect_vec();
+    let variables = |global| (0..n).map(Local).chain(once(Global(global))).map(|v| (v, ()));
+    let mut isoperm = Isoperm::new(
+        source_constraints.clone(),
+        variables(0).chain(once((Global(1), ()))).collect(),
+        target_constraints.clone(),
+        variables(0).collect(),
+    )
+    .unwrap();
+    let mut permutation = isoperm.result();
+    let binding = permutation.next().unwrap();
+    assert!((0..n).all(|i| binding.get_by_left(&Local(i)) == Some(&&Local(rename(i)))));
+    assert_eq!(binding.get_by_left(&Global(0)), Some(&&Global(0)));
+    assert_eq!(binding.get_by_left(&Global(1)), Some(&&Global(1)));
+    assert!(permutation.next().is_none());
+    let variables = |global| (0..n).map(Local).chain(once(Global(0))).map(move |v| (v, global));
+    let mismatch = Isoperm::new(
+        source_constraints,
+        variables(false).collect(),
+        target_constraints,
+        variables(true).collect(),
+    );
+    assert!(mismatch.is_err());
+}
+
+// A random bag over `n` locals, and the same bag with the locals renamed by a
+// random permutation, or slightly altered if `alter` is set.
+fn random_pair(seed: u64, n: i32, m: usize, alter: bool) -> (Bag, Bag) {
+    typed_pair(seed, n, (1, 1), m, alter)
+}
+
+// The bags of `random_pair` over some globals, where the locals are split into
+// some types by their remainders, and renamed by a permutation of each type.
+fn typed_pair(
+    seed: u64,
+    n: i32,
+    (globals, types): (i32, i32),
+    m: usize,
+    alter: bool,
+) -> (Bag, Bag) {
+    let mut next = generator(seed);
+    let variable = |next: &mut dyn FnMut(i32) -> i32| match next(n + globals) {
+        i if i < globals => Global(i),
+        i => Local(i - globals),
+    };
+    let source: Bag = (0..m)
+        .map(|_| match next(3) {
+            0 => ("P", vec![variable(&mut next)]),
+            _ => ("R", vec![variable(&mut next), variable(&mut next)]),
+        })
+        .collect();
+    let mut renaming = (0..n).collect_vec();
+    for t in 0..types {
+        let class = (t..n).step_by(types as usize).map(|i| i as usize).collect_vec();
+        (1..class.len())
+            .rev()
+            .for_each(|i| renaming.swap(class[i], class[next(i as i32 + 1) as usize]));
+    }
+    let mut target: Bag = source
+        .iter()
+        .map(|(r, vs)| {
+            let renamed = vs.iter().map(|v| match *v {
+                Local(i) => Local(renaming[i as usize]),
+                v => v,
+            });
+            (*r, renamed.collect())
+        })
+        .collect();
+    if alter && !target.is_empty() {
+        let index = next(target.len() as i32) as usize;
+        let position = next(target[index].1.len() as i32) as usize;
+        target[index].1[position] = Local(next(n));
+    }
+    (source, target)
+}
+
+// The bags of `random_pair`, where each argument of either bag is replaced by
+// an expression at random.
+fn expression_pair(seed: u64, n: i32, m: usize) -> (Bag, Bag) {
+    blur(seed, random_pair(seed, n, m, false), |_| Expr(0))
+}
+
+// Replace each argument of either bag at random by the expression given for
+// it.
+fn blur(
+    seed: u64,
+    (source, target): (Bag, Bag),
+    expression: fn(Var<i32>) -> Var<i32>,
+) -> (Bag, Bag) {
+    let mut next = generator(!seed);
+    let mut blur = |bag: Bag| {
+        let blurred = bag.into_iter().map(|(r, vs)| {
+            (r, vs.into_iter().map(|v| if next(3) == 0 { expression(v) } else { v }).collect())
+        });
+        blurred.collect()
+    };
+    (blur(source), blur(target))
+}
+
+// The variables of `for_random_pairs`, by their types.
+type Variables = HashMap<Var<i32>, i32>;
+
+// Check random pairs of bags over five locals of two types and two globals of
+// a third type, where the bags of every third seed are altered, and those of
+// every fourth seed use an expression of each type.
+fn for_random_pairs(count: u64, mut check: impl FnMut(u64, Bag, Bag, &Variables)) {
+    let n = 5;
+    let variables = (0..n)
+        .map(|i| (Local(i), i % 2))
+        .chain([(Global(0), 2), (Global(1), 2)])
+        .chain((0..3).map(|t| (Expr(t), t)))
+        .collect();
+    for seed in 0..count {
+        let pair = typed_pair(seed, n, (2, 2), 1 + seed as usize % 7, seed % 3 == 0);
+        let (source, target) = match seed % 4 {
+            1 => blur(seed, pair, |v| Expr(if let Local(i) = v { i % 2 } else { 2 })),
+            _ => pair,
+        };
+        check(seed, source, target, &variables);
+    }
+}
+
+// A small linear congruential generator of numbers below a bound.
+fn generator(seed: u64) -> impl FnMut(i32) -> i32 {
+    let mut state = seed;
+    move |bound: i32| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as i32 % bound
+    }
+}
+
+// Check if two constraints are equal, where expressions are equal to any
+// argument of their type.
+fn agree<T: PartialEq>(
+    types: &HashMap<Var<i32>, T>,
+    (r, vs): &(&str, Vec<Var<i32>>),
+    (q, us): &(&str, Vec<Var<i32>>),
+) -> bool {
+    r == q
+        && vs.len() == us.len()
+        && zip(vs, us).all(|(v, u)| {
+            let wild = matches!(v, Expr(_)) || matches!(u, Expr(_));
+            (wild && types[v] == types[u]) || v == u
+        })
+}
+
+// The size of a largest matching of the constraints of a bag to equal
+// constraints of another bag, found by augmenting paths.
+fn matching<T: PartialEq>(source: &Bag, target: &Bag, types: &HashMap<Var<i32>, T>) -> usize {
+    fn augment<T: PartialEq>(
+        (source, target, types): (&Bag, &Bag, &HashMap<Var<i32>, T>),
+        s: usize,
+        owner: &mut [Option<usize>],
+        seen: &mut [bool],
+    ) -> bool {
+        (0..target.len()).any(|t| {
+            if seen[t] || !agree(types, &source[s], &target[t]) {
+                return false;
+            }
+            seen[t] = true;
+            let free = owner[t].is_none_or(|o| augment((source, target, types), o, owner, seen));
+            free && {
+                owner[t] = Some(s);
+                true
+            }
+        })
+    }
+    let mut owner = vec![None; target.len()];
+    (0..source.len())
+        .filter(|&s| {
+            let seen = &mut vec![false; target.len()];
+            augment((source, target, types), s, &mut owner, seen)
+        })
+        .count()
+}
+
+// Rename the locals of a bag by their images.
+fn renamed(bag: &Bag, images: &[i32]) -> Bag {
+    bag.iter()
+        .map(|(r, vs)| {
+            let renamed = vs.iter().map(|v| match *v {
+                Local(i) => Local(images[i as usize]),
+                v => v,
+            });
+            (*r, renamed.collect())
+        })
+        .collect()
+}
+
+// The bijections of the locals of the variables which keep their types, as
+// the images of the locals by their indices.
+fn bijections<T: PartialEq>(variables: &HashMap<Var<i32>, T>) -> Vec<Vec<i32>> {
+    let n = variables.keys().filter(|v| matches!(v, Local(_))).count() as i32;
+    let typed = |i: i32| &variables[&Local(i)];
+    (0..n)
+        .permutations(n as usize)
+        .filter(|p| (0..n).all(|i| typed(i) == typed(p[i as usize])))
+        .collect()
+}
+
+// Enumerate all bijections of locals, and keep those under which the bags are
+// equal, where expressions are equal to anything.
+fn brute_force<T: PartialEq>(
+    variables: &HashMap<Var<i32>, T>,
+    source: &Bag,
+    target: &Bag,
+) -> HashSet<Solution> {
+    let globals = variables.keys().filter(|v| matches!(v, Global(_))).map(|v| (key(v), key(v)));
+    bijections(variables)
+        .into_iter()
+        .filter(|p| {
+            source.len() == target.len()
+                && matching(&renamed(source, p), target, variables) == target.len()
+        })
+        .map(|p| {
+            let locals =
+                p.iter().enumerate().map(|(i, &j)| (key(&Local(i as i32)), key(&Local(j))));
+            locals.chain(globals.clone()).sorted().collect()
+        })
+        .collect()
+}
+
+#[test]
+fn random_test() {
+    let n = 5;
+    let variables: HashMap<_, _> =
+        (0..n).map(|i| (Local(i), ())).chain(once((Global(0), ()))).collect();
+    for seed in 0..200 {
+        let (source, target) = random_pair(seed, n, 1 + seed as usize % 7, seed % 3 == 0);
+        let expected = brute_force(&variables, &source, &target);
+        let found = Isoperm::new(source, variables.clone(), target, variables.clone())
+            .map(|mut isoperm| isoperm.result().map(canonical).collect_vec())
+            .unwrap_or_default();
+        assert_eq!(found.len(), expected.len(), "seed {}", seed);
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected, "seed {}", seed);
+    }
+    // A permutation may be found by several matchings of the constraints when
+    // expressions are involved, and each leaves its own locals unbound.
+    let variables: HashMap<_, _> = variables.into_iter().chain(once((Expr(0), ()))).collect();
+    for seed in 0..200 {
+        let (source, target) = expression_pair(seed, n, 1 + seed as usize % 7);
+        let expected = brute_force(&variables, &source, &target);
+        let found = Isoperm::new(source, variables.clone(), target, variables.clone())
+            .map(|mut isoperm| isoperm.result().map(canonical).collect::<HashSet<_>>())
+            .unwrap_or_default();
+        assert_eq!(found, expected, "seed {}", seed);
+    }
+}
+
+#[test]
+fn factor_test() {
+    let n = 5;
+    let variables: HashMap<_, _> =
+        (0..n).map(|i| (Local(i), ())).chain(once((Global(0), ()))).collect();
+    for seed in 0..200 {
+        let (source, target) = random_pair(seed, n, 1 + seed as usize % 7, seed % 3 == 0);
+        let expected = brute_force(&variables, &source, &target);
+        let found = Isoperm::builder(source, variables.clone(), target, variables.clone())
+            .factorize()
+            .map(|factorization| {
+                assert_eq!(factorization.count(), Some(expected.len() as u128), "seed {}", seed);
+                factorization.iter().map(canonical).collect_vec()
+            })
+            .unwrap_or_default();
+        assert_eq!(found.len(), expected.len(), "seed {}", seed);
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected, "seed {}", seed);
+    }
+    // The constraints with expressions may be matched across components, and
+    // the locals facing them may be mapped to any local.
+    let variables: HashMap<_, _> = variables.into_iter().chain(once((Expr(0), ()))).collect();
+    for seed in 0..200 {
+        let (source, target) = expression_pair(seed, n, 1 + seed as usize % 7);
+        let factorization =
+            Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+                .factorize();
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            assert!(factorization.is_err(), "seed {}", seed);
+            continue;
+        };
+        let expected = isoperm.result().map(canonical).collect::<HashSet<_>>();
+        let factorization = factorization.unwrap();
+        assert_eq!(factorization.count(), Some(expected.len() as u128), "seed {}", seed);
+        let found = factorization.iter().map(canonical).collect_vec();
+        assert_eq!(found.len(), expected.len(), "seed {}", seed);
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected, "seed {}", seed);
+    }
+    // Two triangles and two free locals.
+    let (mut constraints, variables) = cycle(3, 2);
+    constraints.extend(cycle(3, 0).0.into_iter().map(|(r, vs)| {
+        (r, vs.into_iter().map(|v| if let Local(i) = v { Local(i + 5) } else { v }).collect())
+    }));
+    let variables: HashMap<_, _> =
+        variables.into_iter().chain((5..8).map(|i| (Local(i), true))).collect();
+    let mut isoperm = Isoperm::new(
+        constraints.clone(),
+        variables.clone(),
+        constraints.clone(),
+        variables.clone(),
+    )
+    .unwrap();
+    assert_eq!(isoperm.result().count(), 36);
+    let builder = || {
+        Isoperm::builder(
+            constraints.clone(),
+            variables.clone(),
+            constraints.clone(),
+            variables.clone(),
+        )
+    };
+    let factorization = builder().factorize().unwrap();
+    let factors = factorization.factors().unwrap();
+    assert_eq!(factors.len(), 2);
+    assert_eq!(factors[0].source().count(), 2);
+    assert_eq!(factors[0].automorphisms(), 3);
+    assert_eq!(factors[0].mappings(0, 1).count(), 3);
+    assert_eq!(factorization.count(), Some(2 * 9 * 2));
+    assert_eq!(factorization.iter().map(canonical).unique().count(), 36);
+    // The options that change the permutations are rejected.
+    assert!(builder().forbidden(constraints.clone(), Vec::new()).factorize().is_err());
+    assert!(builder().aliases([(Global(0), Global(0))]).factorize().is_err());
+    assert!(builder().config(Config::new().renaming(true)).factorize().is_err());
+    assert!(builder().config(Config::new().backjumping(false)).factorize().is_ok());
+}
+
+#[test]
+fn strategy_test() {
+    let config = Config::new().strategy(Strategy::ForwardChecking);
+    for_random_pairs(200, |seed, source, target, variables| {
+        let expected = brute_force(variables, &source, &target);
+        let enumerate = |config: Config| {
+            Isoperm::with_config(
+                source.clone(),
+                variables.clone(),
+                target.clone(),
+                variables.clone(),
+                config,
+            )
+            .map(|mut isoperm| isoperm.result().map(canonical).sorted().collect_vec())
+            .unwrap_or_default()
+        };
+        let found = enumerate(config.clone());
+        assert_eq!(found, enumerate(Config::new()), "seed {}", seed);
+        assert_eq!(found.into_iter().collect::<HashSet<_>>(), expected, "seed {}", seed);
+    });
+    // A path of length 3 in a cycle cannot close the path of length 4, which
+    // is told by the domains before the last binding is tried.
+    let variables: HashMap<Var<i32>, _> = (0..5).map(|i| (Local(i), ())).collect();
+    let source = (0..4).map(|i| ("R", vec![Local(i), Local(i + 1)])).collect_vec();
+    let target = (0..4).map(|i| ("R", vec![Local(i), Local((i + 1) % 4)])).collect_vec();
+    let nodes = |strategy| {
+        let config = Config::new().refinement(0).strategy(strategy);
+        let mut isoperm = Isoperm::with_config(
+            source.clone(),
+            variables.clone(),
+            target.clone(),
+            variables.clone(),
+            config,
+        )
+        .unwrap();
+        let mut result = isoperm.result().collect_statistics();
+        assert_eq!(result.by_ref().count(), 0);
+        result.statistics().unwrap().nodes
+    };
+    assert!(nodes(Strategy::ForwardChecking) < nodes(Strategy::GenerateAndTest));
+}
+
+#[test]
+fn backjumping_test() {
+    let solutions = |source: &Bag, target: &Bag, variables: &Variables, config: Config| {
+        let mut isoperm = Isoperm::with_config(
+            source.clone(),
+            variables.clone(),
+            target.clone(),
+            variables.clone(),
+            config,
+        )?;
+        let mut result = isoperm.result().collect_statistics();
+        let found = result.by_ref().map(canonical).collect_vec();
+        Ok::<_, String>((found, result.statistics().unwrap().clone()))
+    };
+    for_random_pairs(200, |seed, source, target, variables| {
+        let config = Config::new().refinement(0).order(GroupOrder::Input);
+        let Ok((expected, _)) =
+            solutions(&source, &target, variables, config.clone().backjumping(false))
+        else {
+            return;
+        };
+        let (found, _) = solutions(&source, &target, variables, config.nogoods(16)).unwrap();
+        assert_eq!(found, expected, "seed {}", seed);
+    });
+    // The markers of `b` cannot be matched without swapping them, which is told
+    // by the group of `P` without trying the bindings of `q`. The failure is
+    // remembered when `a` are swapped.
+    let variables = (0..7).map(|i| (Local(i), 0)).collect();
+    let bag = |b: i32| {
+        let unary = [("A", 0), ("A", 1), ("B", 2), ("B", 3), ("Q", 4), ("Q", 5)];
+        let unary = unary.into_iter().map(|(r, i)| (r, vec![Local(i)]));
+        unary.chain(once(("P", vec![Local(b), Local(6)]))).collect_vec()
+    };
+    let (source, target) = (bag(2), bag(3));
+    let config = Config::new().refinement(0).order(GroupOrder::Input);
+    let (expected, chronological) =
+        solutions(&source, &target, &variables, config.clone().backjumping(false)).unwrap();
+    let (found, statistics) = solutions(&source, &target, &variables, config.nogoods(16)).unwrap();
+    assert_eq!(expected.len(), 4);
+    assert_eq!(found, expected);
+    assert_eq!(chronological.backjumps, 0);
+    assert!(statistics.backjumps > 0);
+    assert!(statistics.nogoods > 0);
+    assert!(statistics.nodes < chronological.nodes);
+}
+
+#[test]
+fn parallel_test() {
+    for_random_pairs(100, |seed, source, target, variables| {
+        let strategy =
+            if seed % 2 == 0 { Strategy::GenerateAndTest } else { Strategy::ForwardChecking };
+        let config = Config::new().strategy(strategy);
+        let Ok(mut isoperm) =
+            Isoperm::with_config(source, variables.clone(), target, variables.clone(), config)
+        else {
+            return;
+        };
+        let expected = isoperm.result().map(canonical).collect_vec();
+        for depth in 0..4 {
+            let parallel = isoperm.parallel().threads(3).depth(depth);
+            assert_eq!(parallel.count(), expected.len(), "seed {}", seed);
+            assert_eq!(parallel.exists(), !expected.is_empty(), "seed {}", seed);
+            let found = parallel.ordered(true).result().map(canonical).collect_vec();
+            assert_eq!(found, expected, "seed {}", seed);
+        }
+        let found = isoperm.parallel().depth(2).result().map(canonical).collect::<HashSet<_>>();
+        assert_eq!(found, expected.into_iter().collect(), "seed {}", seed);
+    });
+    // The split reaches across groups, and the enumeration is left as it is.
+    let (mut constraints, variables) = cycle(6, 2);
+    constraints.push(("S", vec![Local(0)]));
+    constraints.push(("S", vec![Local(3)]));
+    let mut isoperm =
+        Isoperm::new(constraints.clone(), variables.clone(), constraints, variables).unwrap();
+    let first = isoperm.result().next().map(canonical);
+    let expected = isoperm.result().map(canonical).collect_vec();
+    let parallel = isoperm.parallel().threads(4).depth(3).ordered(true);
+    assert_eq!(parallel.count(), expected.len() + 1);
+    let mut found = parallel.result();
+    assert_eq!(found.next().map(canonical), first);
+    assert_eq!(found.map(canonical).collect_vec(), expected);
+    // Dropping the iterator early stops the threads.
+    assert!(isoperm.parallel().depth(2).result().next().is_some());
+    // The subtrees held back for the order are larger than their capacity.
+    let mut isoperm = cycle_isoperm(4, 6);
+    let expected = isoperm.result().map(canonical).collect_vec();
+    let parallel = isoperm.parallel().threads(2).ordered(true);
+    assert_eq!(parallel.count(), expected.len());
+    assert_eq!(parallel.result().map(canonical).collect_vec(), expected);
+    assert!(isoperm.parallel().ordered(true).result().nth(2000).is_some());
+}
+
+#[test]
+fn reset_test() {
+    let mut isoperm = cycle_isoperm(4, 2);
+    let expected = isoperm.result().map(canonical).collect_vec();
+    assert_eq!(expected.len(), 8);
+    assert_eq!(isoperm.result().count(), 0);
+    isoperm.reset();
+    assert_eq!(isoperm.result().map(canonical).collect_vec(), expected);
+    isoperm.reset();
+    isoperm.result().nth(4);
+    let checkpoint = isoperm.checkpoint();
+    // Cursors enumerate from the start whatever the state of the instance.
+    let mut first = isoperm.cursor();
+    let mut second = isoperm.cursor();
+    let interleaved = first.result().take(3).map(canonical).collect_vec();
+    assert_eq!(second.result().map(canonical).collect_vec(), expected);
+    let rest = first.result().map(canonical).collect_vec();
+    assert_eq!([interleaved, rest].concat(), expected);
+    first.reset();
+    assert_eq!(first.result().map(canonical).collect_vec(), expected);
+    second.resume(&checkpoint).unwrap();
+    assert_eq!(second.result().map(canonical).collect_vec(), expected[5..]);
+    assert_eq!(isoperm.result().map(canonical).collect_vec(), expected[5..]);
+}
+
+#[test]
+fn nth_test() {
+    for_random_pairs(100, |seed, source, target, variables| {
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            return;
+        };
+        let expected = isoperm.result().map(canonical).collect_vec();
+        for step in [0, 1, 2, 5, 13] {
+            isoperm.reset();
+            let mut result = isoperm.result();
+            let found = std::iter::from_fn(|| result.nth(step)).map(canonical).collect_vec();
+            let stepped = expected.iter().skip(step).step_by(step + 1).cloned().collect_vec();
+            assert_eq!(found, stepped, "seed {} step {}", seed, step);
+        }
+    });
+    // The orderings of the free locals are skipped without search.
+    let mut isoperm = cycle_isoperm(3, 6);
+    let expected = isoperm.result().map(canonical).collect_vec();
+    assert_eq!(expected.len(), 3 * 720);
+    isoperm.reset();
+    let mut result = isoperm.result().collect_statistics();
+    assert_eq!(result.nth(2000).map(canonical), Some(expected[2000].clone()));
+    assert!(result.statistics().unwrap().nodes < 100);
+    assert_eq!(result.map(canonical).collect_vec(), expected[2001..]);
+    // An interrupted skip is continued by the next call.
+    isoperm.reset();
+    let mut result = isoperm.result().limit(Limit::new().steps(1));
+    let mut found = result.try_nth(1500);
+    while found.is_err() {
+        found = result.try_next();
+    }
+    assert_eq!(found.unwrap().map(canonical), Some(expected[1500].clone()));
+    assert_eq!(isoperm.result().skip(600).map(canonical).next(), Some(expected[2101].clone()));
+}
+
+// A small generator for sampling.
+struct SplitMix(u64);
+
+impl Random for SplitMix {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[test]
+fn sample_test() {
+    let mut random = SplitMix(7);
+    let mut isoperm = cycle_isoperm(4, 2);
+    let expected: HashSet<_> = isoperm.result().map(canonical).collect();
+    assert_eq!(expected.len(), 8);
+    for budget in [100, 0] {
+        let mut sampler = isoperm.sampler(budget);
+        assert_eq!(sampler.exact(), budget > 0);
+        assert_eq!(sampler.count(), sampler.exact().then_some(8));
+        let drawn = (0..4000).map(|_| canonical(sampler.sample(&mut random).unwrap())).counts();
+        assert_eq!(drawn.keys().cloned().collect::<HashSet<_>>(), expected);
+        assert!(drawn.values().all(|&count| (350..650).contains(&count)), "{:?}", drawn);
+        assert!((sampler.estimate(&mut random, 100) - 8.0).abs() < 1e-9);
+    }
+    // The estimate is the mean of the weights of random paths.
+    for_random_pairs(50, |seed, source, target, variables| {
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            return;
+        };
+        let count = isoperm.result().count() as f64;
+        let mut sampler = isoperm.sampler(0);
+        let estimate = sampler.estimate(&mut random, 500);
+        assert!((estimate - count).abs() <= 0.2 * count, "seed {}: {} {}", seed, estimate, count);
+        let drawn = sampler.sample(&mut random);
+        assert_eq!(drawn.is_some(), count > 0.0, "seed {}", seed);
+    });
+    // Each permutation of the groups leaves its own locals unbound when
+    // expressions are involved, and is drawn by the number of their orderings.
+    for_random_pairs(200, |seed, source, target, variables| {
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            return;
+        };
+        let expected = isoperm.result().map(canonical).counts();
+        let count = expected.values().sum::<usize>();
+        let mut sampler = isoperm.sampler(1000);
+        assert_eq!(sampler.count(), Some(count as u128), "seed {}", seed);
+        if count == 0 || count > 30 {
+            return;
+        }
+        let draws = 200 * count;
+        let drawn = (0..draws).map(|_| canonical(sampler.sample(&mut random).unwrap())).counts();
+        assert!(drawn.keys().all(|p| expected.contains_key(p)), "seed {}", seed);
+        assert!(
+            expected.iter().all(|(p, &times)| {
+                let drawn = drawn.get(p).copied().unwrap_or(0) as f64;
+                (drawn - 200.0 * times as f64).abs() <= 60.0 * times as f64
+            }),
+            "seed {}",
+            seed
+        );
+    });
+}
+
+#[test]
+fn optimize_test() {
+    let cost = |v: &Var<i32>, u: &Var<i32>| ((key(v).1 * 7 + key(u).1 * 3) % 5) as f64;
+    for_random_pairs(100, |seed, source, target, variables| {
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            return;
+        };
+        let mut expected = isoperm
+            .result()
+            .map(|p| {
+                let total =
+                    p.iter().filter(|(v, _)| matches!(v, Local(_))).map(|(v, u)| cost(v, u));
+                (total.sum::<f64>(), canonical(p))
+            })
+            .collect_vec();
+        expected.sort_by(|a, b| a.0.total_cmp(&b.0));
+        expected.truncate(3);
+        let optimum = isoperm.optimize(3, Limit::new(), cost);
+        assert!(optimum.interrupted.is_none());
+        let found = optimum.best.into_iter().map(|(p, cost)| (cost, canonical(p))).collect_vec();
+        assert_eq!(found, expected, "seed {}", seed);
+    });
+    // Keep the names of as many locals as possible.
+    let isoperm = cycle_isoperm(6, 3);
+    let renamed = |v: &Var<i32>, u: &Var<i32>| (v != u) as i32 as f64;
+    let optimum = isoperm.optimize(2, Limit::new(), renamed);
+    assert_eq!(optimum.best.iter().map(|(_, cost)| *cost).collect_vec(), [0.0, 2.0]);
+    assert!(optimum.best[0].0.iter().all(|(v, u)| v == u));
+    let optimum = isoperm.optimize(1, Limit::new().steps(3), renamed);
+    assert_eq!(optimum.interrupted, Some(Interrupt::Steps));
+    assert!(isoperm.optimize(0, Limit::new(), renamed).best.is_empty());
+}
+
+#[test]
+fn align_test() {
+    for_random_pairs(200, |seed, source, target, variables| {
+        // The most constraints kept by a bijection of the locals of each type.
+        let largest = bijections(variables)
+            .iter()
+            .map(|p| matching(&renamed(&source, p), &target, variables))
+            .max()
+            .unwrap();
+        let alignment =
+            Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+                .align(Limit::new())
+                .unwrap();
+        assert!(alignment.interrupted.is_none());
+        assert_eq!(alignment.matched.len(), largest, "seed {}", seed);
+        assert_eq!(alignment.source.constraints.len(), source.len() - largest);
+        assert_eq!(alignment.target.constraints.len(), target.len() - largest);
+        for ((r, vs), (q, us)) in &alignment.matched {
+            let mapped = vs.iter().map(|v| *alignment.mapping.get_by_left(v).unwrap_or(v));
+            let mapped = (*r, mapped.collect());
+            assert!(agree(variables, &mapped, &(*q, us.clone())), "seed {}", seed);
+        }
+        let locals = |side: &Bag| {
+            side.iter().flat_map(|(_, vs)| vs).filter(|v| matches!(v, Local(_))).unique().count()
+        };
+        let n = bijections(variables)[0].len();
+        assert_eq!(alignment.mapping.len() + alignment.source.locals.len(), n);
+        assert!(alignment.mapping.len() <= locals(&source));
+    });
+    // A triangle and a path share two edges, leaving an edge and no local.
+    let variables: HashMap<Var<i32>, _> = (0..3).map(|i| (Local(i), ())).collect();
+    let triangle = (0..3).map(|i| ("R", vec![Local(i), Local((i + 1) % 3)])).collect_vec();
+    let path = (0..2).map(|i| ("R", vec![Local(i), Local(i + 1)])).collect_vec();
+    let alignment =
+        Isoperm::builder(triangle, variables.clone(), path, variables).align(Limit::new()).unwrap();
+    assert_eq!(alignment.matched.len(), 2);
+    assert_eq!(alignment.source.constraints.len(), 1);
+    assert!(alignment.target.constraints.is_empty());
+    assert_eq!(alignment.mapping.len(), 3);
+    assert!(alignment.source.locals.is_empty() && alignment.target.locals.is_empty());
+    // Globals are paired by their aliases, or renamed, as in `build()`, and
+    // forbidden constraints are rejected.
+    let variables: HashMap<Var<i32>, _> =
+        [(Global(0), ()), (Global(1), ()), (Local(0), ())].into_iter().collect();
+    let source = vec![("R", vec![Global(0), Local(0)]), ("S", vec![Global(1)])];
+    let target = vec![("R", vec![Global(1), Local(0)]), ("S", vec![Global(0)])];
+    let builder =
+        || Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone());
+    assert!(builder().align(Limit::new()).unwrap().matched.is_empty());
+    let aliases = [(Global(0), Global(1)), (Global(1), Global(0))];
+    assert_eq!(builder().aliases(aliases).align(Limit::new()).unwrap().matched.len(), 2);
+    assert!(builder().aliases(aliases).diff(Limit::new()).unwrap().is_empty());
+    let renamed = Config::new().renaming(true);
+    let alignment = builder().config(renamed.clone()).align(Limit::new()).unwrap();
+    assert_eq!(alignment.matched.len(), 2);
+    assert_eq!(alignment.mapping.get_by_left(&Global(0)), Some(&Global(1)));
+    assert!(builder().config(renamed).explain(Limit::new()).unwrap().is_none());
+    assert!(builder().forbidden(source.clone(), Vec::new()).align(Limit::new()).is_err());
+}
+
+#[test]
+fn diff_test() {
+    // Isomorphic bags with free locals do not differ.
+    let (constraints, variables) = cycle(4, 2);
+    let diff = Isoperm::builder(constraints.clone(), variables.clone(), constraints, variables)
+        .diff(Limit::new())
+        .unwrap();
+    assert!(diff.is_empty() && diff.interrupted.is_none());
+    assert_eq!(diff.mapping.len(), 6);
+    assert!(diff.to_string().is_empty());
+    // A triangle and a path differ by an edge.
+    let variables: HashMap<Var<i32>, _> = (0..3).map(|i| (Local(i), ())).collect();
+    let triangle = (0..3).map(|i| ("R", vec![Local(i), Local((i + 1) % 3)])).collect_vec();
+    let path = (0..2).map(|i| ("R", vec![Local(i), Local(i + 1)])).collect_vec();
+    let diff =
+        Isoperm::builder(triangle, variables.clone(), path, variables).diff(Limit::new()).unwrap();
+    assert!(!diff.is_empty());
+    assert_eq!(diff.removed.len(), 1);
+    assert!(diff.added.is_empty() && diff.types.is_empty());
+    assert_eq!(diff.groups.len(), 1);
+    assert_eq!(diff.groups[0].key, ("R", vec![(), ()]));
+    assert_eq!((diff.groups[0].source, diff.groups[0].target), (3, 2));
+    let text = diff.to_string();
+    assert!(text.lines().any(|line| line.starts_with("- \"R\"")));
+    assert!(text.lines().any(|line| line.starts_with("group \"R\"")));
+    // An extra local of another type is left unpaired.
+    let (constraints, variables) = cycle(3, 0);
+    let mut more = variables.clone();
+    more.insert(Local(3), false);
+    let diff = Isoperm::builder(constraints.clone(), variables, constraints, more)
+        .diff(Limit::new())
+        .unwrap();
+    assert!(diff.removed.is_empty() && diff.added.is_empty() && diff.groups.is_empty());
+    assert_eq!(diff.unpaired_target, vec![Local(3)]);
+    assert_eq!(diff.types.len(), 1);
+    assert_eq!((diff.types[0].key, diff.types[0].source, diff.types[0].target), (false, 0, 1));
+    assert!(diff.to_string().contains("type false: 0 source locals, 1 target locals"));
+}
+
+#[test]
+fn explain_test() {
+    let fits = |source: &Bag, target: &Bag, variables: &Variables| {
+        let alignment =
+            Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+                .align(Limit::new())
+                .unwrap();
+        alignment.source.constraints.is_empty()
+    };
+    let mut cores = 0;
+    for_random_pairs(200, |seed, source, target, variables| {
+        let core =
+            Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+                .explain(Limit::new())
+                .unwrap();
+        let Some(core) = core else {
+            assert!(fits(&source, &target, variables), "seed {}", seed);
+            return;
+        };
+        cores += 1;
+        assert!(core.interrupted.is_none());
+        assert!(!fits(&core.constraints, &target, variables), "seed {}", seed);
+        for i in 0..core.constraints.len() {
+            let mut rest = core.constraints.clone();
+            rest.remove(i);
+            assert!(fits(&rest, &target, variables), "seed {}", seed);
+        }
+    });
+    assert!(cores > 0);
+    // A triangle does not fit a path with a loop, and every edge of it is
+    // needed.
+    let variables: HashMap<Var<i32>, _> = (0..3).map(|i| (Local(i), ())).collect();
+    let triangle = (0..3).map(|i| ("R", vec![Local(i), Local((i + 1) % 3)])).collect_vec();
+    let mut path = (0..2).map(|i| ("R", vec![Local(i), Local(i + 1)])).collect_vec();
+    path.push(("R", vec![Local(2), Local(1)]));
+    let core =
+        Isoperm::builder(triangle.clone(), variables.clone(), path.clone(), variables.clone())
+            .explain(Limit::new())
+            .unwrap()
+            .unwrap();
+    assert_eq!(core.constraints, triangle);
+    // A path fits the triangle.
+    path.pop();
+    assert!(Isoperm::builder(path, variables.clone(), triangle, variables)
+        .explain(Limit::new())
+        .unwrap()
+        .is_none());
+}
+
+#[test]
+fn project_test() {
+    // The supports are the targets of the permutations, where expressions
+    // leave their own locals unbound in each permutation of the groups.
+    for_random_pairs(200, |seed, source, target, variables| {
+        let Ok(mut isoperm) = Isoperm::new(source, variables.clone(), target, variables.clone())
+        else {
+            return;
+        };
+        let projection = isoperm.project(Limit::new());
+        assert!(projection.interrupted.is_none());
+        let supports = projection
+            .supports
+            .into_iter()
+            .map(|(v, support)| (*v, support.into_iter().copied().collect::<HashSet<_>>()))
+            .collect::<HashMap<_, _>>();
+        let locals = variables.keys().filter(|v| matches!(v, Local(_)));
+        let mut expected: HashMap<_, HashSet<Var<i32>>> =
+            locals.map(|v| (*v, HashSet::new())).collect();
+        isoperm.result().for_each(|p| {
+            p.iter().filter(|(v, _)| matches!(v, Local(_))).for_each(|(v, u)| {
+                expected.get_mut(v).unwrap().insert(**u);
+            });
+        });
+        assert_eq!(supports, expected, "seed {}", seed);
+    });
+    // Every local of a cycle can be mapped to every local, and the free locals
+    // to the free locals.
+    let mut isoperm = cycle_isoperm(5, 2);
+    let projection = isoperm.project(Limit::new());
+    for (v, support) in &projection.supports {
+        let expected = if key(v).1 < 5 { 0..5 } else { 5..7 };
+        assert_eq!(support.len(), expected.len());
+        assert!(support.iter().all(|u| expected.contains(&key(u).1)));
+    }
+    assert_eq!(isoperm.result().count(), 5 * 2);
+    let projection = isoperm.project(Limit::new().steps(3));
+    assert_eq!(projection.interrupted, Some(Interrupt::Steps));
+}
+
+#[test]
+fn forbidden_test() {
+    let mut state = 7u64;
+    let mut next = move |bound: i32| {
+        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (state >> 33) as i32 % bound
+    };
+    let mut rejected = 0;
+    for_random_pairs(100, |seed, source, target, variables| {
+        let declared = variables.keys().filter(|v| !matches!(v, Expr(_)));
+        let declared = declared.copied().sorted_by_key(key).collect_vec();
+        let variable =
+            |next: &mut dyn FnMut(i32) -> i32| declared[next(declared.len() as i32) as usize];
+        let mut fact = || match next(3) {
+            0 => ("P", vec![variable(&mut next)]),
+            1 => ("Q", vec![variable(&mut next)]),
+            _ => ("R", vec![variable(&mut next), variable(&mut next)]),
+        };
+        let forbidden: (Bag, Bag) = ((0..2).map(|_| fact()).collect(), vec![fact()]);
+        let Ok(mut isoperm) =
+            Isoperm::new(source.clone(), variables.clone(), target.clone(), variables.clone())
+        else {
+            return;
+        };
+        // A forbidden constraint is present once its arguments are bound, where
+        // expressions of the bag match anything.
+        let present = |bag: &Bag, (r, vs): &(&str, Vec<Var<i32>>), map: &HashMap<_, _>| {
+            let Some(mapped) = vs.iter().map(|v| map.get(&key(v))).collect::<Option<Vec<_>>>()
+            else {
+                return false;
+            };
+            bag.iter().any(|(q, us)| {
+                q == r && zip(us, &mapped).all(|(u, v)| matches!(u, Expr(_)) || key(u) == **v)
+            })
+        };
+        let all = isoperm.result().map(canonical).collect_vec();
+        let expected = all
+            .iter()
+            .filter(|p| {
+                let forward = p.iter().copied().collect::<HashMap<_, _>>();
+                let backward = p.iter().map(|&(v, u)| (u, v)).collect::<HashMap<_, _>>();
+                !forbidden.0.iter().any(|f| present(&target, f, &forward))
+                    && !forbidden.1.iter().any(|f| present(&source, f, &backward))
+            })
+            .cloned()
+            .collect_vec();
+        for config in [
+            Config::new(),
+            Config::new().backjumping(false),
+            Config::new().strategy(Strategy::ForwardChecking).nogoods(16),
+        ] {
+            let mut isoperm = Isoperm::builder(
+                source.clone(),
+                variables.clone(),
+                target.clone(),
+                variables.clone(),
+            )
+            .forbidden(forbidden.0.clone(), forbidden.1.clone())
+            .config(config)
+            .build()
+            .unwrap();
+            let found = isoperm.result().map(canonical).collect_vec();
+            assert_eq!(found.len(), expected.len(), "seed {}", seed);
+            let found = found.into_iter().collect::<HashSet<_>>();
+            assert_eq!(found, expected.iter().cloned().collect(), "seed {}", seed);
+        }
+        rejected += all.len() - expected.len();
+    });
+    assert!(rejected > 0);
+    // Forbidden constraints never present on the other side reject nothing,
+    // and those present between globals reject everything.
+    let (constraints, variables) = cycle(4, 2);
+    let forbidden = (
+        vec![("R", vec![Local(1), Local(0)])],
+        vec![("S", vec![Local(4)]), ("R", vec![Local(0), Local(2)])],
+    );
+    let mut isoperm = Isoperm::builder(
+        constraints.clone(),
+        variables.clone(),
+        constraints.clone(),
+        variables.clone(),
+    )
+    .forbidden(forbidden.0, forbidden.1)
+    .build()
+    .unwrap();
+    assert_eq!(isoperm.result().count(), 8);
+    let forbidden = (vec![("R", vec![Local(4), Local(5)])], vec![("T", vec![Global(0)])]);
+    let mut isoperm = Isoperm::builder(
+        constraints.clone(),
+        variables.clone(),
+        constraints.clone(),
+        variables.clone(),
+    )
+    .forbidden(forbidden.0, forbidden.1)
+    .build()
+    .unwrap();
+    assert_eq!(isoperm.result().count(), 8);
+    let forbidden: (Bag, Bag) = (vec![("R", vec![Global(0), Global(0)])], Vec::new());
+    let mut looped = constraints.clone();
+    looped.push(("R", vec![Global(0), Global(0)]));
+    let mut isoperm = Isoperm::builder(looped.clone(), variables.clone(), looped, variables)
+        .forbidden(forbidden.0, forbidden.1)
+        .build()
+        .unwrap();
+    assert_eq!(isoperm.result().count(), 0);
+}
+
+#[test]
+fn optional_test() {
+    let optional = |n, free, source, target| {
+        let (constraints, variables) = cycle(n, free);
+        Isoperm::builder(constraints.clone(), variables.clone(), constraints, variables)
+            .optional(source, target)
+            .build()
+    };
+    let isoperm = optional(
+        4,
+        2,
+        vec![
+            ("S", vec![Local(0), Local(4)], 2.0),
+            ("T", vec![Local(1)], 1.0),
+            ("R", vec![Local(0), Local(2)], 5.0),
+        ],
+        vec![("S", vec![Local(2), Local(5)], 3.0), ("T", vec![Local(3)], 1.0)],
+    )
+    .unwrap();
+    // The optional constraints are left out of the matching.
+    let ranking = isoperm.rank(100, Limit::new());
+    assert!(ranking.interrupted.is_none());
+    assert_eq!(ranking.ranked.len(), 8);
+    let weights = ranking.ranked.iter().map(|(_, weight)| *weight).collect_vec();
+    assert_eq!(weights, [3.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0]);
+    let best = &ranking.ranked[0].0;
+    assert_eq!(best.get_by_left(&Local(0)), Some(&&Local(2)));
+    assert_eq!(best.get_by_left(&Local(4)), Some(&&Local(5)));
+    let ranking = isoperm.rank(1, Limit::new());
+    assert_eq!(ranking.ranked.len(), 1);
+    assert_eq!(canonical(ranking.ranked[0].0.clone()), canonical(best.clone()));
+    let ranking = isoperm.rank(1, Limit::new().steps(3));
+    assert_eq!(ranking.interrupted, Some(Interrupt::Steps));
+    assert!(optional(4, 2, vec![("S", vec![Local(9)], 1.0)], Vec::new()).is_err());
+    // The orderings of the unconfined locals are pruned as the groups are.
+    let isoperm =
+        optional(4, 10, vec![("P", vec![Local(4)], 1.0)], vec![("P", vec![Local(13)], 2.0)])
+            .unwrap();
+    let ranking = isoperm.rank(3, Limit::new().steps(10_000));
+    assert!(ranking.interrupted.is_none());
+    assert!(ranking
+        .ranked
+        .iter()
+        .all(|(p, weight)| { *weight == 1.0 && p.get_by_left(&Local(4)) == Some(&&Local(13)) }));
+    // The ranking agrees with the weights of every permutation.
+    let n = 5;
+    let variables: HashMap<_, _> = (0..n + 3)
+        .map(|i| (Local(i), (i >= n) as i32))
+        .chain([(Global(0), 2), (Expr(0), 2)])
+        .collect();
+    for seed in 0..100 {
+        let m = 1 + seed as usize % 7;
+        let (bags, (optional, _)) = match seed % 2 {
+            0 => (random_pair(seed, n, m, seed % 3 == 0), random_pair(!seed, n, 4, false)),
+            _ => (expression_pair(seed, n, m), expression_pair(!seed, n, 4)),
+        };
+        let mut next = generator(seed);
+        let mut weighted = |bag: Bag| {
+            let unconfined = ("P", vec![Local(n + next(3))]);
+            let weighted = bag.into_iter().chain(once(unconfined));
+            weighted.map(|(r, vs)| (r, vs, 1.0 + next(4) as f64)).collect_vec()
+        };
+        let source = weighted(optional.clone()).into_iter().sorted_by(|a, b| b.2.total_cmp(&a.2));
+        let source = source.collect_vec();
+        let target = weighted(optional).into_iter().sorted_by(|a, b| b.2.total_cmp(&a.2));
+        let target = target.collect_vec();
+        let Ok(mut isoperm) =
+            Isoperm::builder(bags.0, variables.clone(), bags.1, variables.clone())
+                .optional(source.clone(), target.clone())
+                .build()
+        else {
+            continue;
+        };
+        let preserved = |p: &BiMap<&Var<i32>, &Var<i32>>| {
+            let mut used = vec![false; target.len()];
+            let preserved = source.iter().filter_map(|(r, vs, weight)| {
+                let mapped = vs.iter().map(|v| match v {
+                    Expr(_) => Some(*v),
+                    _ => p.get_by_left(v).map(|u| **u),
+                });
+                let mapped = mapped.collect::<Option<Vec<_>>>()?;
+                let t = (0..target.len()).find(|&t| {
+                    let (other, us, _) = &target[t];
+                    !used[t]
+                        && other == r
+                        && us.len() == mapped.len()
+                        && zip(us, &mapped).all(|pair| match pair {
+                            (Expr(_), _) | (_, Expr(_)) => true,
+                            (u, w) => u == w,
+                        })
+                })?;
+                used[t] = true;
+                Some(weight.min(target[t].2))
+            });
+            preserved.sum::<f64>()
+        };
+        let expected = isoperm.result().map(|p| (preserved(&p), canonical(p))).collect_vec();
+        let expected = expected.into_iter().sorted_by(|a, b| b.0.total_cmp(&a.0)).take(4);
+        let ranking = isoperm.rank(4, Limit::new());
+        assert!(ranking.interrupted.is_none());
+        let found = ranking.ranked.into_iter().map(|(p, weight)| (weight, canonical(p)));
+        assert_eq!(found.collect_vec(), expected.collect_vec(), "seed {}", seed);
+    }
+}
+
+#[test]
+fn renaming_test() {
+    let variables: HashMap<Var<i32>, _> = [
+        (Global(0), false),
+        (Global(1), false),
+        (Global(2), true),
+        (Local(0), true),
+        (Local(1), true),
+    ]
+    .into_iter()
+    .collect();
+    let source = vec![("R", vec![Global(0), Local(0)]), ("S", vec![Global(1)])];
+    let target = vec![("R", vec![Global(1), Local(1)]), ("S", vec![Global(0)])];
+    let renamed = |config: Config| {
+        Isoperm::with_config(
+            source.clone(),
+            variables.clone(),
+            target.clone(),
+            variables.clone(),
+            config,
+        )
+    };
+    assert_eq!(renamed(Config::new()).unwrap().result().count(), 0);
+    let mut isoperm = renamed(Config::new().renaming(true)).unwrap();
+    let found = isoperm.result().collect_vec();
+    assert_eq!(found.len(), 1);
+    let (local, global) = Isoperm::split(found[0].clone());
+    let pairs = |mapping: BiMap<&Var<i32>, &Var<i32>>| {
+        mapping.into_iter().map(|(v, u)| (*v, *u)).sorted_by_key(|(v, _)| key(v)).collect_vec()
+    };
+    assert_eq!(pairs(local), [(Local(0), Local(1)), (Local(1), Local(0))]);
+    assert_eq!(
+        pairs(global),
+        [(Global(0), Global(1)), (Global(1), Global(0)), (Global(2), Global(2))]
+    );
+    // Globals are only renamed to globals of the same type.
+    let mut unbalanced = variables.clone();
+    unbalanced.insert(Global(2), false);
+    let result = Isoperm::with_config(
+        source.clone(),
+        variables,
+        target,
+        unbalanced,
+        Config::new().renaming(true),
+    );
+    assert!(result.is_err());
+}
+
+#[test]
+fn aliases_test() {
+    let variables: HashMap<Var<i32>, _> =
+        [(Global(0), false), (Global(1), false), (Global(2), true), (Local(0), true)]
+            .into_iter()
+            .collect();
+    let source = vec![("R", vec![Global(0), Local(0)]), ("S", vec![Global(1), Global(2)])];
+    let target = vec![("R", vec![Global(1), Local(0)]), ("S", vec![Global(0), Global(2)])];
+    let aliased = |aliases: Vec<(Var<i32>, Var<i32>)>| {
+        Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+            .aliases(aliases)
+            .build()
+    };
+    assert_eq!(aliased(Vec::new()).unwrap().result().count(), 0);
+    let mut isoperm = aliased(vec![(Global(0), Global(1)), (Global(1), Global(0))]).unwrap();
+    let found = isoperm.result().collect_vec();
+    assert_eq!(found.len(), 1);
+    let (local, global) = Isoperm::split(found[0].clone());
+    let pairs = |mapping: BiMap<&Var<i32>, &Var<i32>>| {
+        mapping.into_iter().map(|(v, u)| (*v, *u)).sorted_by_key(|(v, _)| key(v)).collect_vec()
+    };
+    assert_eq!(pairs(local), [(Local(0), Local(0))]);
+    assert_eq!(
+        pairs(global),
+        [(Global(0), Global(1)), (Global(1), Global(0)), (Global(2), Global(2))]
+    );
+    // The aliased target global is also paired with the source global of the
+    // same name.
+    assert!(aliased(vec![(Global(0), Global(1))]).is_err());
+    assert!(aliased(vec![(Global(0), Global(2))]).is_err());
+    assert!(aliased(vec![(Global(0), Global(3))]).is_err());
+    assert!(aliased(vec![(Local(0), Global(1))]).is_err());
+    // Aliased globals stay paired when the others are renamed.
+    let mut renamed =
+        Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+            .aliases([(Global(0), Global(1))])
+            .config(Config::new().renaming(true))
+            .build()
+            .unwrap();
+    let found = renamed.result().collect_vec();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].get_by_left(&Global(0)), Some(&&Global(1)));
+    // Forbidden constraints are matched through the aliased globals.
+    let forbidden = |constraint: (&'static str, Vec<Var<i32>>)| {
+        Isoperm::builder(source.clone(), variables.clone(), target.clone(), variables.clone())
+            .aliases([(Global(0), Global(1)), (Global(1), Global(0))])
+            .forbidden(vec![constraint], Vec::new())
+            .build()
+            .unwrap()
+            .result()
+            .count()
+    };
+    assert_eq!(forbidden(("R", vec![Global(0), Local(0)])), 0);
+    assert_eq!(forbidden(("R", vec![Global(1), Local(0)])), 1);
+}
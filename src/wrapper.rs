@@ -1,9 +1,16 @@
+use crate::checkpoint::Checkpoint;
+use crate::config::Config;
 use crate::enumerator::StatementEnumerator;
-use crate::statement::{Constraint, Variable};
+use crate::environment::Environment;
+use crate::optional::{Optional, Weighted};
+use crate::search::{Context, Interrupt, Limit, Observer, Statistics};
+use crate::statement::{fingerprint, Constraint, Variable};
 use bimap::BiMap;
 use itertools::Itertools;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::iter::zip;
+use std::ops::ControlFlow;
 
 /// # The wrapper variable enum.
 /// There are three types of variables:
@@ -23,7 +30,8 @@ where
 {
     /// An expression variable could potentially be matched to anything.
     Expr(W),
-    /// A global variable can only be matched to itself.
+    /// A global variable can only be matched to itself, or to other global
+    /// variables of the same type if the globals are renamed.
     Global(V),
     /// A local variable can only be matched to other local variables.
     Local(U),
@@ -44,6 +52,20 @@ where
     }
 }
 
+/// A permutation from source variables to target variables.
+pub type Permutation<'t, U, V = U, W = U> = BiMap<&'t Var<U, V, W>, &'t Var<U, V, W>>;
+/// The mappings of the local and of the global variables in a permutation.
+pub type Split<'t, U, V = U, W = U> = (Permutation<'t, U, V, W>, Permutation<'t, U, V, W>);
+
+pub(crate) type Translation<T, U, V, W> = BiMap<(Variable, T), Var<U, V, W>>;
+pub(crate) type Lookup<U, V, W> = BiMap<Variable, Var<U, V, W>>;
+type Constraints<R, U, V, W> = Vec<(R, Vec<Var<U, V, W>>)>;
+type Side<R, T, U, V, W> = (Constraints<R, U, V, W>, HashMap<Var<U, V, W>, T>);
+// The numbered variables of each side, where the renamed globals are locals of
+// their own types, with the pairs of globals.
+pub(crate) type Native<T, U, V, W> =
+    (Translation<(bool, T), U, V, W>, Translation<(bool, T), U, V, W>, Vec<(Variable, Variable)>);
+
 /// # The wrapper permutation struct.
 /// In order to construct an iterator of all potential permutations, first
 /// construct an instance of `Isoperm` class by provide the two
@@ -55,9 +77,31 @@ where
     V: Eq + Hash + PartialEq,
     W: Eq + Hash + PartialEq,
 {
-    source_translation: BiMap<Variable, Var<U, V, W>>,
-    target_translation: BiMap<Variable, Var<U, V, W>>,
-    permutation: StatementEnumerator,
+    pub(crate) source_translation: Lookup<U, V, W>,
+    pub(crate) target_translation: Lookup<U, V, W>,
+    pub(crate) permutation: StatementEnumerator,
+    pub(crate) optional: Optional,
+}
+
+/// # The builder struct.
+/// A builder collects the inputs of an `Isoperm` instance, where the forbidden
+/// constraints, the optional constraints, the aliases and the configuration
+/// are optional, and creates the instance by `build()`. The inputs can also be
+/// factorized, aligned, compared or explained as a whole.
+pub struct Builder<R, T, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    pub(crate) source: Side<R, T, U, V, W>,
+    pub(crate) target: Side<R, T, U, V, W>,
+    pub(crate) source_forbidden: Constraints<R, U, V, W>,
+    pub(crate) target_forbidden: Constraints<R, U, V, W>,
+    pub(crate) source_optional: Vec<Weighted<R, U, V, W>>,
+    pub(crate) target_optional: Vec<Weighted<R, U, V, W>>,
+    pub(crate) aliases: HashMap<Var<U, V, W>, Var<U, V, W>>,
+    pub(crate) config: Config,
 }
 
 impl<U, V, W> Isoperm<U, V, W>
@@ -83,54 +127,176 @@ where
         S: IntoIterator<Item = (R, Vec<Var<U, V, W>>)>,
         T: Eq + Hash,
     {
-        let source_native_variables =
-            Isoperm::transform_variables(source_variables, &BiMap::new())?;
-        let target_native_variables =
-            Isoperm::transform_variables(target_variables, &source_native_variables)?;
-        let (source_types, source_translation) = Isoperm::split_mapping(source_native_variables);
-        let (target_types, target_translation) = Isoperm::split_mapping(target_native_variables);
-        let mut constraint_record = HashMap::new();
-        let source_native_constraints = Isoperm::transform_constraints(
+        Isoperm::with_config(
             source_constraints,
-            &source_translation,
-            &mut constraint_record,
-        )?;
-        let target_native_constraints = Isoperm::transform_constraints(
+            source_variables,
             target_constraints,
-            &target_translation,
-            &mut constraint_record,
-        )?;
-        let permutation = StatementEnumerator::new(
-            source_native_constraints,
-            &source_types,
-            target_native_constraints,
-            &target_types,
-        )?;
-        Ok(Self { source_translation, target_translation, permutation })
+            target_variables,
+            Config::default(),
+        )
+    }
+
+    /// Create a new `Isoperm` instance as in `new`, with the search tuned by
+    /// the given configuration.
+    pub fn with_config<R, S, T>(
+        source_constraints: S,
+        source_variables: HashMap<Var<U, V, W>, T>,
+        target_constraints: S,
+        target_variables: HashMap<Var<U, V, W>, T>,
+        config: Config,
+    ) -> Result<Self, String>
+    where
+        R: Eq + Hash,
+        S: IntoIterator<Item = (R, Vec<Var<U, V, W>>)>,
+        T: Eq + Hash,
+    {
+        Isoperm::builder(source_constraints, source_variables, target_constraints, target_variables)
+            .config(config)
+            .build()
+    }
+
+    /// Returns a builder of an `Isoperm` instance with the inputs as in `new`,
+    /// where the other inputs can be given together.
+    pub fn builder<R, S, T>(
+        source_constraints: S,
+        source_variables: HashMap<Var<U, V, W>, T>,
+        target_constraints: S,
+        target_variables: HashMap<Var<U, V, W>, T>,
+    ) -> Builder<R, T, U, V, W>
+    where
+        R: Eq + Hash,
+        S: IntoIterator<Item = (R, Vec<Var<U, V, W>>)>,
+        T: Eq + Hash,
+    {
+        Builder {
+            source: (source_constraints.into_iter().collect(), source_variables),
+            target: (target_constraints.into_iter().collect(), target_variables),
+            source_forbidden: Vec::new(),
+            target_forbidden: Vec::new(),
+            source_optional: Vec::new(),
+            target_optional: Vec::new(),
+            aliases: HashMap::new(),
+            config: Config::default(),
+        }
     }
 
-    fn transform_variables<T>(
+    // Number the variables of each side, and pair the globals, unless they
+    // are renamed, or by their aliases.
+    pub(crate) fn prepare<T>(
+        source_variables: HashMap<Var<U, V, W>, T>,
+        target_variables: HashMap<Var<U, V, W>, T>,
+        aliases: &HashMap<Var<U, V, W>, Var<U, V, W>>,
+        renaming: bool,
+    ) -> Result<Native<T, U, V, W>, String>
+    where
+        T: Eq + Hash,
+    {
+        let source_native_variables =
+            Isoperm::rename_globals(Isoperm::transform_variables(source_variables), renaming);
+        let target_native_variables =
+            Isoperm::rename_globals(Isoperm::transform_variables(target_variables), renaming);
+        // The renamed globals of each type must be as many on both sides.
+        let count = Isoperm::count_renamed;
+        if count(&source_native_variables) != count(&target_native_variables) {
+            return Err(String::from("Global variable mismatch."));
+        }
+        let global =
+            Isoperm::pair_globals(&source_native_variables, &target_native_variables, aliases)?;
+        Ok((source_native_variables, target_native_variables, global))
+    }
+
+    // Number the variables in the order of their fingerprints, which does not
+    // depend on the iteration order of the hashmap.
+    pub(crate) fn transform_variables<T>(
         variables: HashMap<Var<U, V, W>, T>,
-        reference: &BiMap<(Variable, T), Var<U, V, W>>,
-    ) -> Result<BiMap<(Variable, T), Var<U, V, W>>, String>
+    ) -> Translation<T, U, V, W>
     where
         T: Eq + Hash,
     {
         variables
             .into_iter()
+            .sorted_by_cached_key(|(v, _)| fingerprint(v))
             .enumerate()
-            .map(|(signature, (v, t))| match reference.get_by_right(&v) {
-                Some((vr, tr)) if matches!(&v, Var::Global(_)) => (&t == tr)
-                    .then(|| ((vr.clone(), t), v))
-                    .ok_or(String::from("Global variable type mismatch.")),
-                _ => Ok(((v.transform(signature), t), v)),
+            .map(|(signature, (v, t))| ((v.transform(signature), t), v))
+            .collect()
+    }
+
+    // Turn the global variables into locals of their own types if they are
+    // renamed, so that they are only bound to each other. The types of the
+    // other variables are left apart.
+    pub(crate) fn rename_globals<T>(
+        translation: Translation<T, U, V, W>,
+        renaming: bool,
+    ) -> Translation<(bool, T), U, V, W>
+    where
+        T: Eq + Hash,
+    {
+        translation
+            .into_iter()
+            .map(|((v, t), var)| match v {
+                Variable::Global(i) if renaming => ((Variable::Local(i), (true, t)), var),
+                _ => ((v, (false, t)), var),
             })
             .collect()
     }
 
-    fn transform_constraints<R, S>(
+    // Count the renamed globals of each type.
+    fn count_renamed<T>(translation: &Translation<(bool, T), U, V, W>) -> HashMap<&T, usize>
+    where
+        T: Eq + Hash,
+    {
+        let renamed = translation.left_values().filter(|(_, (renamed, _))| *renamed);
+        renamed.map(|(_, (_, t))| t).counts()
+    }
+
+    /// Split a permutation into the mappings of the local variables and of the
+    /// global variables.
+    pub fn split<'t>(permutation: Permutation<'t, U, V, W>) -> Split<'t, U, V, W> {
+        permutation.into_iter().partition(|(v, _)| !matches!(v, Var::Global(_)))
+    }
+
+    // Pair the global variables declared on both sides, unless they are
+    // renamed, and the aliased global variables, which must have the same
+    // types. Each target global is paired at most once. The pairs are sorted,
+    // so that they do not depend on the order of the translations.
+    pub(crate) fn pair_globals<T>(
+        source: &Translation<T, U, V, W>,
+        target: &Translation<T, U, V, W>,
+        aliases: &HashMap<Var<U, V, W>, Var<U, V, W>>,
+    ) -> Result<Vec<(Variable, Variable)>, String>
+    where
+        T: Eq + Hash,
+    {
+        let aliased = aliases
+            .iter()
+            .map(|(v, u)| match (v, u, source.get_by_right(v), target.get_by_right(u)) {
+                (Var::Global(_), Var::Global(_), Some(vs), Some(ut)) => Ok((vs, ut)),
+                _ => Err(String::from("Undeclared global variable in alias.")),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut pairs = source
+            .iter()
+            .filter(|((vs, _), v)| matches!(vs, Variable::Global(_)) && !aliases.contains_key(v))
+            .filter_map(|(vs, v)| target.get_by_right(v).map(|ut| (vs, ut)))
+            .chain(aliased)
+            .map(|((vs, ts), (vt, tt))| {
+                (ts == tt)
+                    .then_some((*vs, *vt))
+                    .ok_or(String::from("Global variable type mismatch."))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        pairs.sort();
+        pairs
+            .iter()
+            .map(|(_, u)| u)
+            .all_unique()
+            .then_some(pairs)
+            .ok_or(String::from("Global variable paired twice."))
+    }
+
+    pub(crate) fn transform_constraints<R, S>(
         constraints: S,
-        variables: &BiMap<Variable, Var<U, V, W>>,
+        variables: &Lookup<U, V, W>,
         record: &mut HashMap<R, usize>,
     ) -> Result<Vec<Constraint>, String>
     where
@@ -140,7 +306,7 @@ where
         constraints.into_iter().try_fold(Vec::new(), |mut transformed, (signature, arguments)| {
             arguments
                 .into_iter()
-                .map(|v| variables.get_by_right(&v).map(|vl| vl.clone()))
+                .map(|v| variables.get_by_right(&v).copied())
                 .collect::<Option<_>>()
                 .map(|vs| {
                     let frame = record.len();
@@ -152,24 +318,194 @@ where
         })
     }
 
-    fn split_mapping<T>(
-        translation: BiMap<(Variable, T), Var<U, V, W>>,
-    ) -> (HashMap<Variable, T>, BiMap<Variable, Var<U, V, W>>)
+    pub(crate) fn split_mapping<T>(
+        translation: Translation<T, U, V, W>,
+    ) -> (HashMap<Variable, T>, Lookup<U, V, W>)
     where
         T: Eq + Hash,
     {
-        translation.into_iter().map(|((vl, t), vr)| ((vl.clone(), t), (vl, vr))).unzip()
+        translation.into_iter().map(|((vl, t), vr)| ((vl, t), (vl, vr))).unzip()
+    }
+
+    /// Returns a checkpoint of the enumeration state. The enumeration
+    /// continues after the last returned permutation once the checkpoint is
+    /// resumed.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.permutation.checkpoint()
+    }
+
+    /// Resume the enumeration from a checkpoint. The checkpoint must have been
+    /// taken from an `Isoperm` instance constructed from the same inputs.
+    pub fn resume(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        self.permutation.restore(checkpoint)
+    }
+
+    /// Start the enumeration over from the first permutation. The inputs are
+    /// not prepared again.
+    pub fn reset(&mut self) {
+        self.permutation.restart();
+    }
+
+    /// Returns a cursor that enumerates the permutations from the start,
+    /// independently of the enumeration of the instance and of other cursors.
+    pub fn cursor(&self) -> Cursor<'_, U, V, W> {
+        let mut permutation = self.permutation.clone();
+        permutation.restart();
+        Cursor {
+            source_translation: &self.source_translation,
+            target_translation: &self.target_translation,
+            permutation,
+        }
     }
 
     /// Returns the iterator of all possible permutations. Each permutation is
     /// represented as a `Bimap`, where the left values are source variables,
     /// while the right values are target variables.
-    pub fn result(&mut self) -> Isopermutation<U, V, W> {
-        Isopermutation {
-            source: &self.source_translation,
-            target: &self.target_translation,
-            perm: &mut self.permutation,
-        }
+    pub fn result(&mut self) -> Isopermutation<'_, U, V, W> {
+        Isopermutation::new(
+            &self.source_translation,
+            &self.target_translation,
+            &mut self.permutation,
+        )
+    }
+}
+
+impl<R, T, U, V, W> Builder<R, T, U, V, W>
+where
+    R: Eq + Hash,
+    T: Eq + Hash,
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Sets the configuration tuning the search.
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Adds forbidden constraints on each side, which are given as the
+    /// constraints are. A permutation is rejected if it maps a forbidden
+    /// constraint of either side to a constraint present on the other side.
+    /// Expression variables in a forbidden constraint are matched to anything.
+    pub fn forbidden<F>(mut self, source: F, target: F) -> Self
+    where
+        F: IntoIterator<Item = (R, Vec<Var<U, V, W>>)>,
+    {
+        self.source_forbidden.extend(source);
+        self.target_forbidden.extend(target);
+        self
+    }
+
+    /// Adds aliases of source global variables, where each source global in
+    /// the alias table is paired with the given target global instead of the
+    /// one of the same name. The other globals are paired as they are. If the
+    /// globals are renamed, the aliased pairs are kept fixed. Building returns
+    /// an error if an alias refers to an undeclared global variable, pairs
+    /// globals of different types, or pairs a target global twice.
+    pub fn aliases<A>(mut self, aliases: A) -> Self
+    where
+        A: IntoIterator<Item = (Var<U, V, W>, Var<U, V, W>)>,
+    {
+        self.aliases.extend(aliases);
+        self
+    }
+
+    /// Create the `Isoperm` instance, or returns an error as `new` does, or if
+    /// some forbidden or optional constraint uses an undeclared variable.
+    pub fn build(self) -> Result<Isoperm<U, V, W>, String> {
+        let Builder {
+            source: (source_constraints, source_variables),
+            target: (target_constraints, target_variables),
+            source_forbidden,
+            target_forbidden,
+            source_optional,
+            target_optional,
+            aliases,
+            config,
+        } = self;
+        let (source_native_variables, target_native_variables, global) =
+            Isoperm::prepare(source_variables, target_variables, &aliases, config.renaming)?;
+        let (source_types, source_translation) = Isoperm::split_mapping(source_native_variables);
+        let (target_types, target_translation) = Isoperm::split_mapping(target_native_variables);
+        let mut constraint_record = HashMap::new();
+        let source_native_constraints = Isoperm::transform_constraints(
+            source_constraints,
+            &source_translation,
+            &mut constraint_record,
+        )?;
+        let target_native_constraints = Isoperm::transform_constraints(
+            target_constraints,
+            &target_translation,
+            &mut constraint_record,
+        )?;
+        let forbidden = (
+            Isoperm::transform_constraints(
+                source_forbidden,
+                &source_translation,
+                &mut constraint_record,
+            )?,
+            Isoperm::transform_constraints(
+                target_forbidden,
+                &target_translation,
+                &mut constraint_record,
+            )?,
+        );
+        let optional = Optional::new(
+            (source_optional, &source_translation),
+            (target_optional, &target_translation),
+        )?;
+        let permutation = StatementEnumerator::new(
+            source_native_constraints,
+            &source_types,
+            target_native_constraints,
+            &target_types,
+            global,
+            forbidden,
+            &config,
+        )?;
+        Ok(Isoperm { source_translation, target_translation, permutation, optional })
+    }
+}
+
+/// # The cursor struct.
+/// A cursor keeps an enumeration state of its own over the inputs prepared by
+/// an `Isoperm` instance, so that several enumerations can be made at once.
+pub struct Cursor<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source_translation: &'t Lookup<U, V, W>,
+    target_translation: &'t Lookup<U, V, W>,
+    permutation: StatementEnumerator,
+}
+
+impl<'t, U, V, W> Cursor<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Start the enumeration of the cursor over from the first permutation.
+    pub fn reset(&mut self) {
+        self.permutation.restart();
+    }
+
+    /// Returns a checkpoint of the enumeration state, as in `Isoperm`.
+    pub fn checkpoint(&self) -> Checkpoint {
+        self.permutation.checkpoint()
+    }
+
+    /// Resume the enumeration from a checkpoint, as in `Isoperm`.
+    pub fn resume(&mut self, checkpoint: &Checkpoint) -> Result<(), String> {
+        self.permutation.restore(checkpoint)
+    }
+
+    /// Returns the iterator of the remaining permutations, as in `Isoperm`.
+    pub fn result(&mut self) -> Isopermutation<'_, U, V, W> {
+        Isopermutation::new(self.source_translation, self.target_translation, &mut self.permutation)
     }
 }
 
@@ -180,9 +516,341 @@ where
     V: Eq + Hash + PartialEq,
     W: Eq + Hash + PartialEq,
 {
-    source: &'t BiMap<Variable, Var<U, V, W>>,
-    target: &'t BiMap<Variable, Var<U, V, W>>,
+    source: &'t Lookup<U, V, W>,
+    target: &'t Lookup<U, V, W>,
     perm: &'t mut StatementEnumerator,
+    limit: Limit,
+    interrupt: Option<Interrupt>,
+    statistics: Option<Statistics>,
+    observer: Option<&'t mut dyn Observer>,
+    // The number of permutations left to skip.
+    pending: usize,
+}
+
+impl<'t, U, V, W> Isopermutation<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    fn new(
+        source: &'t Lookup<U, V, W>,
+        target: &'t Lookup<U, V, W>,
+        perm: &'t mut StatementEnumerator,
+    ) -> Self {
+        Self {
+            source,
+            target,
+            perm,
+            limit: Limit::new(),
+            interrupt: None,
+            statistics: None,
+            observer: None,
+            pending: 0,
+        }
+    }
+
+    /// Bound the effort spent on finding each permutation.
+    pub fn limit(mut self, limit: Limit) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Enable the collection of search statistics.
+    pub fn collect_statistics(mut self) -> Self {
+        self.statistics = Some(Statistics::default());
+        self
+    }
+
+    /// Returns the statistics collected so far, or `None` if the collection
+    /// is not enabled.
+    pub fn statistics(&self) -> Option<&Statistics> {
+        self.statistics.as_ref()
+    }
+
+    /// Notify the observer of every binding pushed or popped by the search.
+    pub fn observe(mut self, observer: &'t mut dyn Observer) -> Self {
+        self.observer = Some(observer);
+        self
+    }
+
+    /// Returns the reason why the last call to `next()` was cut short, or
+    /// `None` if it was not.
+    pub fn interrupted(&self) -> Option<Interrupt> {
+        self.interrupt
+    }
+
+    /// Find the next permutation. Returns `Ok(None)` if there are no more
+    /// permutations, or an error if the search is cut short by the limit. An
+    /// interrupted search can be continued by calling this method again.
+    pub fn try_next(&mut self) -> Result<Option<Permutation<'t, U, V, W>>, Interrupt> {
+        Ok(self.advance(false)?.then(|| {
+            translate(self.source, self.target, self.perm.environment().pairs()).collect()
+        }))
+    }
+
+    /// Skip the given number of permutations and find the next one, as in
+    /// `try_next`. The orderings of the local variables used by no constraint
+    /// are skipped at once, without visiting them. An interrupted search is
+    /// continued by calling `try_next`, which then finds the permutation asked
+    /// for.
+    pub fn try_nth(&mut self, n: usize) -> Result<Option<Permutation<'t, U, V, W>>, Interrupt> {
+        self.pending = self.pending.saturating_add(n);
+        self.try_next()
+    }
+
+    /// Visit the remaining permutations without copying them. The visitor is
+    /// lent a view of each permutation, and stops the search by returning
+    /// `ControlFlow::Break`, whose value is then returned. Returns `Ok(None)`
+    /// if the permutations are exhausted, or an error if the search is cut
+    /// short by the limit.
+    pub fn for_each_solution<B>(
+        &mut self,
+        mut visitor: impl FnMut(SolutionView<'_, U, V, W>) -> ControlFlow<B>,
+    ) -> Result<Option<B>, Interrupt> {
+        while self.advance(false)? {
+            if let ControlFlow::Break(value) = visitor(self.view()) {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Turn into an iterator of symbolic permutations, where the local
+    /// variables used by no constraint are left as free groups.
+    pub fn symbolic(self) -> SymbolicPermutations<'t, U, V, W> {
+        SymbolicPermutations { inner: self }
+    }
+
+    // Find the next permutation, or only its bindings of constrained locals,
+    // and keep it in the enumerator.
+    fn advance(&mut self, core: bool) -> Result<bool, Interrupt> {
+        let mut context = Context::new(
+            &self.limit,
+            self.statistics.as_mut(),
+            self.observer.as_mut().map(|o| &mut **o as _),
+        );
+        if core {
+            self.perm.next_core(&mut context)
+        } else {
+            self.perm.skip(&mut self.pending, &mut context)
+        }
+    }
+
+    fn view(&self) -> SolutionView<'_, U, V, W> {
+        SolutionView {
+            source: self.source,
+            target: self.target,
+            environment: self.perm.environment(),
+        }
+    }
+}
+
+/// A group of source local variables and target local variables of the same
+/// type, which are mapped to each other in any order.
+pub type FreeGroup<'t, U, V = U, W = U> = (Vec<&'t Var<U, V, W>>, Vec<&'t Var<U, V, W>>);
+
+/// # The symbolic permutation struct.
+/// A symbolic permutation maps the constrained local variables and the global
+/// variables as a permutation, and leaves the rest of the local variables as
+/// free groups. It stands for all the permutations that extend the mapping
+/// with a bijection of each free group.
+#[derive(Clone, Debug)]
+pub struct SymbolicPermutation<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// The mapping of the constrained local variables and global variables.
+    pub core: Permutation<'t, U, V, W>,
+    /// The groups of local variables used by no constraint.
+    pub free: Vec<FreeGroup<'t, U, V, W>>,
+}
+
+impl<'t, U, V, W> SymbolicPermutation<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns the iterator of all permutations that the symbolic permutation
+    /// stands for.
+    pub fn expand(&self) -> impl Iterator<Item = Permutation<'t, U, V, W>> + '_ {
+        let arrangements =
+            self.free.iter().map(|(_, target)| target.iter().permutations(target.len()));
+        // An empty product has a single element.
+        let empty = self.free.is_empty().then(Vec::new);
+        let arrangements = arrangements.multi_cartesian_product().chain(empty);
+        arrangements.map(move |arrangement| {
+            let free = zip(&self.free, arrangement).flat_map(|((source, _), target)| {
+                zip(source.iter().copied(), target.into_iter().copied())
+            });
+            self.core.iter().map(|(&s, &t)| (s, t)).chain(free).collect()
+        })
+    }
+}
+
+/// The iterator of symbolic permutations.
+pub struct SymbolicPermutations<'t, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    inner: Isopermutation<'t, U, V, W>,
+}
+
+impl<'t, U, V, W> SymbolicPermutations<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns the statistics collected so far, as in `Isopermutation`.
+    pub fn statistics(&self) -> Option<&Statistics> {
+        self.inner.statistics()
+    }
+
+    /// Returns the reason why the last call to `next()` was cut short, or
+    /// `None` if it was not.
+    pub fn interrupted(&self) -> Option<Interrupt> {
+        self.inner.interrupt
+    }
+
+    /// Find the next symbolic permutation, as in `Isopermutation::try_next`.
+    pub fn try_next(&mut self) -> Result<Option<SymbolicPermutation<'t, U, V, W>>, Interrupt> {
+        let inner = &mut self.inner;
+        if !inner.advance(true)? {
+            return Ok(None);
+        }
+        let (source, target) = (inner.source, inner.target);
+        let free = inner
+            .perm
+            .free()
+            .map(|(s, t)| {
+                (
+                    s.iter().map(|v| source.get_by_left(v).unwrap()).collect(),
+                    t.iter().map(|u| target.get_by_left(u).unwrap()).collect(),
+                )
+            })
+            .collect();
+        let core = translate(source, target, inner.perm.environment().pairs()).collect();
+        Ok(Some(SymbolicPermutation { core, free }))
+    }
+}
+
+impl<'t, U, V, W> Iterator for SymbolicPermutations<'t, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    type Item = SymbolicPermutation<'t, U, V, W>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let result = self.try_next();
+        self.inner.interrupt = result.as_ref().err().copied();
+        result.ok().flatten()
+    }
+}
+
+/// # The solution view struct.
+/// A view borrows the permutation last found by the search, from source
+/// variables to target variables. Global variables are mapped to themselves,
+/// unless they are renamed or aliased.
+pub struct SolutionView<'v, U, V = U, W = U>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    source: &'v Lookup<U, V, W>,
+    target: &'v Lookup<U, V, W>,
+    environment: &'v Environment,
+}
+
+impl<'v, U, V, W> SolutionView<'v, U, V, W>
+where
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    /// Returns the target variable that a source variable is mapped to.
+    pub fn target_of(&self, variable: &Var<U, V, W>) -> Option<&'v Var<U, V, W>> {
+        let bound = self.source.get_by_right(variable).and_then(|v| self.environment.target_of(v));
+        match (variable, bound) {
+            (_, Some(u)) => self.target.get_by_left(&u),
+            (Var::Global(_), None) => self.declared(variable),
+            _ => None,
+        }
+    }
+
+    /// Returns the source variable that a target variable is mapped from.
+    pub fn source_of(&self, variable: &Var<U, V, W>) -> Option<&'v Var<U, V, W>> {
+        let bound = self.target.get_by_right(variable).and_then(|u| self.environment.source_of(u));
+        match (variable, bound) {
+            (_, Some(v)) => self.source.get_by_left(&v),
+            (Var::Global(_), None) => self.declared(variable),
+            _ => None,
+        }
+    }
+
+    /// Returns the pairs of source and target variables in the permutation.
+    pub fn iter(&self) -> impl Iterator<Item = (&'v Var<U, V, W>, &'v Var<U, V, W>)> + 'v {
+        let (source, target, environment) = (self.source, self.target, self.environment);
+        let bound = environment.pairs().filter(|(v, _)| !matches!(v, Variable::Expr(_)));
+        let bound =
+            bound.map(|(v, u)| (source.get_by_left(&v).unwrap(), target.get_by_left(&u).unwrap()));
+        // The global variables left unbound on either side are mapped to
+        // themselves, as in `translate`.
+        let global = |v: &Variable| matches!(v, Variable::Global(_));
+        let source_unbound =
+            source.iter().filter(move |(v, _)| global(v) && environment.target_of(v).is_none());
+        let target_unbound = target.iter().filter(move |(u, w)| {
+            global(u) && environment.source_of(u).is_none() && !source.contains_right(w)
+        });
+        bound.chain(source_unbound.chain(target_unbound).map(|(_, v)| (v, v)))
+    }
+
+    // Returns a global variable left unbound as it is declared, which is
+    // mapped to itself.
+    fn declared(&self, variable: &Var<U, V, W>) -> Option<&'v Var<U, V, W>> {
+        let declared = |side: &'v Lookup<U, V, W>| {
+            side.get_by_right(variable).and_then(|v| side.get_by_left(v))
+        };
+        declared(self.source).or_else(|| declared(self.target))
+    }
+}
+
+// Translate the bindings of an environment to pairs of wrapper variables. The
+// global variables left unbound are mapped to themselves.
+pub(crate) fn translate<'s, I, U, V, W>(
+    source: &'s Lookup<U, V, W>,
+    target: &'s Lookup<U, V, W>,
+    pairs: I,
+) -> impl Iterator<Item = (&'s Var<U, V, W>, &'s Var<U, V, W>)>
+where
+    I: Iterator<Item = (Variable, Variable)>,
+    U: Eq + Hash + PartialEq,
+    V: Eq + Hash + PartialEq,
+    W: Eq + Hash + PartialEq,
+{
+    let pairs = pairs.filter(|(v, _)| !matches!(v, Variable::Expr(_))).collect_vec();
+    let (source_bound, target_bound): (HashSet<_>, HashSet<_>) = pairs.iter().copied().unzip();
+    let unbound = |side: &'s Lookup<U, V, W>, bound: HashSet<Variable>| {
+        let global = side.iter().filter(|(v, _)| matches!(v, Variable::Global(_)));
+        global.filter(move |(v, _)| !bound.contains(v)).map(|(_, v)| v)
+    };
+    let global = unbound(source, source_bound)
+        .chain(unbound(target, target_bound).filter(|v| !source.contains_right(v)))
+        .map(|v| (v, v));
+    pairs
+        .into_iter()
+        .map(|(v, u)| (source.get_by_left(&v).unwrap(), target.get_by_left(&u).unwrap()))
+        .chain(global)
+        .collect_vec()
+        .into_iter()
 }
 
 impl<'t, U, V, W> Iterator for Isopermutation<'t, U, V, W>
@@ -191,23 +859,19 @@ where
     V: Eq + Hash + PartialEq,
     W: Eq + Hash + PartialEq,
 {
-    type Item = BiMap<&'t Var<U, V, W>, &'t Var<U, V, W>>;
+    type Item = Permutation<'t, U, V, W>;
 
+    // Returns `None` both when the permutations are exhausted and when the
+    // search is cut short, which can be told apart by `interrupted()`.
     fn next(&mut self) -> Option<Self::Item> {
-        self.perm.next().map(|binding| {
-            binding
-                .into_iter()
-                .map(|(t, s)| {
-                    (
-                        self.source
-                            .get_by_left(&s)
-                            .unwrap_or_else(|| self.target.get_by_left(&s).unwrap()),
-                        self.target
-                            .get_by_left(&t)
-                            .unwrap_or_else(|| self.source.get_by_left(&t).unwrap()),
-                    )
-                })
-                .collect()
-        })
+        let result = self.try_next();
+        self.interrupt = result.as_ref().err().copied();
+        result.ok().flatten()
+    }
+
+    fn nth(&mut self, n: usize) -> Option<Self::Item> {
+        let result = self.try_nth(n);
+        self.interrupt = result.as_ref().err().copied();
+        result.ok().flatten()
     }
 }